@@ -0,0 +1,611 @@
+//! A binary cellular automaton shared by the seating (day 11) and hex tile
+//! (day 24) puzzles. Each puzzle supplies a topology describing which cells
+//! exist and who their neighbors are, along with the birth/survival rules
+//! keyed on the number of live neighbors. The pocket dimension puzzle (day
+//! 17) folds its lattice along mirror planes, which breaks the symmetric
+//! neighbor relation the engine relies on, so it only shares the lattice
+//! neighborhood.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+pub trait Topology {
+    type Cell: Copy + Eq + Hash;
+
+    /// Call `f` with every neighbor of `cell`. The neighbor relation must be
+    /// symmetric: live neighbor counts are accumulated by scattering from
+    /// each live cell to its neighbors.
+    fn for_each_neighbor<F: FnMut(Self::Cell)>(&self, cell: Self::Cell, f: F);
+
+    /// Every cell of a bounded topology, or `None` if the topology is
+    /// unbounded. Only bounded topologies support rules where cells are born
+    /// with zero live neighbors.
+    fn cells(&self) -> Option<Vec<Self::Cell>> {
+        None
+    }
+}
+
+/// Which live neighbor counts cause a dead cell to come alive and a live cell
+/// to stay alive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rules {
+    birth: u128,
+    survival: u128,
+}
+
+impl Rules {
+    pub fn new<B, S>(birth: B, survival: S) -> Self
+    where
+        B: IntoIterator<Item = usize>,
+        S: IntoIterator<Item = usize>,
+    {
+        fn to_mask(counts: impl IntoIterator<Item = usize>) -> u128 {
+            counts.into_iter().fold(0, |mask, count| {
+                assert!(count < 128, "neighbor count {} is too large", count);
+                mask | 1 << count
+            })
+        }
+        Rules {
+            birth: to_mask(birth),
+            survival: to_mask(survival),
+        }
+    }
+
+    /// Conway's game of life: born with 3 live neighbors, survives with 2 or 3.
+    pub fn conway() -> Self {
+        Rules::new([3], [2, 3])
+    }
+
+    pub fn next_state(&self, alive: bool, live_neighbors: usize) -> bool {
+        let mask = if alive { self.survival } else { self.birth };
+        live_neighbors < 128 && mask & 1 << live_neighbors != 0
+    }
+}
+
+/// How a call to `Automaton::run` finished.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The state stopped changing at this generation.
+    Stable { generation: usize },
+    /// The state at `start + period` repeats the state at `start`.
+    Cycle { start: usize, period: usize },
+    /// Neither happened within the allowed number of generations.
+    Running,
+}
+
+pub struct Automaton<T: Topology> {
+    topology: T,
+    rules: Rules,
+    live: HashSet<T::Cell>,
+    generation: usize,
+}
+
+impl<T: Topology> Automaton<T> {
+    pub fn new<I>(topology: T, rules: Rules, live: I) -> Self
+    where
+        I: IntoIterator<Item = T::Cell>,
+    {
+        Automaton {
+            topology,
+            rules,
+            live: live.into_iter().collect(),
+            generation: 0,
+        }
+    }
+
+    pub fn topology(&self) -> &T {
+        &self.topology
+    }
+
+    pub fn live_cells(&self) -> &HashSet<T::Cell> {
+        &self.live
+    }
+
+    pub fn is_alive(&self, cell: T::Cell) -> bool {
+        self.live.contains(&cell)
+    }
+
+    pub fn population(&self) -> usize {
+        self.live.len()
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Advance a single generation, returning whether any cell changed.
+    pub fn step(&mut self) -> bool {
+        let mut live_neighbors: HashMap<T::Cell, usize> = HashMap::new();
+        for &cell in &self.live {
+            self.topology.for_each_neighbor(cell, |neighbor| {
+                *live_neighbors.entry(neighbor).or_insert(0) += 1;
+            });
+        }
+
+        let mut next = HashSet::with_capacity(self.live.len());
+        if self.rules.next_state(false, 0) {
+            let cells = self
+                .topology
+                .cells()
+                .expect("rules that give birth with no live neighbors need a bounded topology");
+            for cell in cells {
+                let count = live_neighbors.get(&cell).copied().unwrap_or(0);
+                if self.rules.next_state(self.live.contains(&cell), count) {
+                    next.insert(cell);
+                }
+            }
+        } else {
+            for (&cell, &count) in &live_neighbors {
+                if self.rules.next_state(self.live.contains(&cell), count) {
+                    next.insert(cell);
+                }
+            }
+            // Live cells without any live neighbors don't show up above.
+            for &cell in &self.live {
+                if !live_neighbors.contains_key(&cell) && self.rules.next_state(true, 0) {
+                    next.insert(cell);
+                }
+            }
+        }
+
+        self.generation += 1;
+        let changed = next != self.live;
+        self.live = next;
+        changed
+    }
+
+    pub fn step_n(&mut self, generations: usize) {
+        for _ in 0..generations {
+            self.step();
+        }
+    }
+
+    /// Step until the state reaches a fixed point or starts repeating, for at
+    /// most `max_generations` generations.
+    pub fn run(&mut self, max_generations: usize) -> Outcome {
+        let mut history: History<T::Cell> = HashMap::new();
+        for _ in 0..max_generations {
+            let prev = self.live.clone();
+            let prev_generation = self.generation;
+            if !self.step() {
+                return Outcome::Stable {
+                    generation: prev_generation,
+                };
+            }
+            history
+                .entry(fingerprint(&prev))
+                .or_default()
+                .push((prev_generation, prev));
+            if let Some(&(start, _)) = history
+                .get(&fingerprint(&self.live))
+                .and_then(|seen| seen.iter().find(|(_, state)| *state == self.live))
+            {
+                return Outcome::Cycle {
+                    start,
+                    period: self.generation - start,
+                };
+            }
+        }
+        Outcome::Running
+    }
+}
+
+// Previous states bucketed by an order independent hash of their live cells.
+type History<C> = HashMap<u64, Vec<(usize, HashSet<C>)>>;
+
+fn fingerprint<C: Hash>(cells: &HashSet<C>) -> u64 {
+    cells.iter().fold(0u64, |acc, cell| {
+        let mut hasher = DefaultHasher::new();
+        cell.hash(&mut hasher);
+        acc.wrapping_add(hasher.finish())
+    })
+}
+
+/// An automaton over a bounded topology with every cell's neighbors
+/// precomputed into a flat list. Live neighbor counts are kept up to date as
+/// cells change, and each generation only re-evaluates cells that changed or
+/// had a neighbor change. The candidate and change lists are reused between
+/// generations so stepping doesn't allocate.
+pub struct DenseAutomaton<C> {
+    rules: Rules,
+    cells: Vec<C>,
+    index: HashMap<C, usize>,
+    // The neighbors of cell i are neighbors[neighbor_offsets[i]..neighbor_offsets[i + 1]].
+    neighbor_offsets: Vec<usize>,
+    neighbors: Vec<usize>,
+    alive: Vec<bool>,
+    live_neighbors: Vec<usize>,
+    population: usize,
+    candidates: Vec<usize>,
+    is_candidate: Vec<bool>,
+    changes: Vec<usize>,
+    generation: usize,
+}
+
+impl<C: Copy + Eq + Hash> DenseAutomaton<C> {
+    /// Panics if `topology` is unbounded.
+    pub fn new<T, I>(topology: &T, rules: Rules, live: I) -> Self
+    where
+        T: Topology<Cell = C>,
+        I: IntoIterator<Item = C>,
+    {
+        let cells = topology
+            .cells()
+            .expect("dense automatons need a bounded topology");
+        let index: HashMap<C, usize> = cells
+            .iter()
+            .enumerate()
+            .map(|(idx, &cell)| (cell, idx))
+            .collect();
+        let mut neighbor_offsets = Vec::with_capacity(cells.len() + 1);
+        let mut neighbors = Vec::new();
+        neighbor_offsets.push(0);
+        for &cell in &cells {
+            topology.for_each_neighbor(cell, |neighbor| neighbors.push(index[&neighbor]));
+            neighbor_offsets.push(neighbors.len());
+        }
+
+        let mut automaton = DenseAutomaton {
+            rules,
+            alive: vec![false; cells.len()],
+            live_neighbors: vec![0; cells.len()],
+            population: 0,
+            candidates: (0..cells.len()).collect(),
+            is_candidate: vec![true; cells.len()],
+            changes: Vec::new(),
+            generation: 0,
+            cells,
+            index,
+            neighbor_offsets,
+            neighbors,
+        };
+        for cell in live {
+            let idx = automaton.index[&cell];
+            if !automaton.alive[idx] {
+                automaton.flip(idx);
+            }
+        }
+        automaton
+    }
+
+    fn neighbors_of(&self, idx: usize) -> &[usize] {
+        &self.neighbors[self.neighbor_offsets[idx]..self.neighbor_offsets[idx + 1]]
+    }
+
+    fn flip(&mut self, idx: usize) {
+        let now_alive = !self.alive[idx];
+        self.alive[idx] = now_alive;
+        if now_alive {
+            self.population += 1;
+        } else {
+            self.population -= 1;
+        }
+        for pos in self.neighbor_offsets[idx]..self.neighbor_offsets[idx + 1] {
+            let neighbor = self.neighbors[pos];
+            if now_alive {
+                self.live_neighbors[neighbor] += 1;
+            } else {
+                self.live_neighbors[neighbor] -= 1;
+            }
+        }
+    }
+
+    fn mark_candidate(&mut self, idx: usize) {
+        if !self.is_candidate[idx] {
+            self.is_candidate[idx] = true;
+            self.candidates.push(idx);
+        }
+    }
+
+    pub fn is_alive(&self, cell: C) -> bool {
+        self.index.get(&cell).is_some_and(|&idx| self.alive[idx])
+    }
+
+    pub fn live_cells(&self) -> impl Iterator<Item = C> + '_ {
+        self.cells
+            .iter()
+            .zip(&self.alive)
+            .filter(|&(_, &alive)| alive)
+            .map(|(&cell, _)| cell)
+    }
+
+    /// The cells that `cell` counts as neighbors, or `None` if `cell` isn't
+    /// part of the topology.
+    pub fn neighbors(&self, cell: C) -> Option<impl Iterator<Item = C> + '_> {
+        let idx = *self.index.get(&cell)?;
+        Some(self.neighbors_of(idx).iter().map(move |&n| self.cells[n]))
+    }
+
+    pub fn population(&self) -> usize {
+        self.population
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Advance a single generation, returning whether any cell changed.
+    pub fn step(&mut self) -> bool {
+        // Decide every change against the current state before applying any.
+        let mut candidates = std::mem::take(&mut self.candidates);
+        let mut changes = std::mem::take(&mut self.changes);
+        changes.clear();
+        for &idx in &candidates {
+            self.is_candidate[idx] = false;
+            let alive = self.alive[idx];
+            if self.rules.next_state(alive, self.live_neighbors[idx]) != alive {
+                changes.push(idx);
+            }
+        }
+        candidates.clear();
+        self.candidates = candidates;
+
+        for &idx in &changes {
+            self.flip(idx);
+            self.mark_candidate(idx);
+            for pos in self.neighbor_offsets[idx]..self.neighbor_offsets[idx + 1] {
+                self.mark_candidate(self.neighbors[pos]);
+            }
+        }
+        let changed = !changes.is_empty();
+        self.changes = changes;
+        self.generation += 1;
+        changed
+    }
+
+    /// Step until nothing changes, for at most `max_generations`
+    /// generations. Returns the generation that reached the fixed point.
+    pub fn run_until_stable(&mut self, max_generations: usize) -> Option<usize> {
+        for _ in 0..max_generations {
+            let generation = self.generation;
+            if !self.step() {
+                return Some(generation);
+            }
+        }
+        None
+    }
+}
+
+/// How neighbors are found on a `BoundedGrid`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GridNeighborhood {
+    /// The cells immediately surrounding a cell in the eight directions.
+    Adjacent,
+    /// The first cell visible in each of the eight directions, looking past
+    /// any gaps in the grid.
+    LineOfSight,
+}
+
+/// A rectangular grid where some positions may be gaps that hold no cell.
+/// Cells are identified by their row major index.
+#[derive(Debug, Clone)]
+pub struct BoundedGrid {
+    width: usize,
+    height: usize,
+    present: Vec<bool>,
+    neighborhood: GridNeighborhood,
+}
+
+impl BoundedGrid {
+    const DIRECTIONS: [(isize, isize); 8] = [
+        (-1, -1),
+        (-1, 0),
+        (-1, 1),
+        (0, -1),
+        (0, 1),
+        (1, -1),
+        (1, 0),
+        (1, 1),
+    ];
+
+    pub fn new(width: usize, present: Vec<bool>, neighborhood: GridNeighborhood) -> Self {
+        assert!(
+            width > 0 && present.len().is_multiple_of(width),
+            "grid is not rectangular"
+        );
+        BoundedGrid {
+            width,
+            height: present.len() / width,
+            present,
+            neighborhood,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether there is a cell at `idx` rather than a gap.
+    pub fn is_present(&self, idx: usize) -> bool {
+        self.present[idx]
+    }
+}
+
+impl Topology for BoundedGrid {
+    type Cell = usize;
+
+    fn for_each_neighbor<F: FnMut(usize)>(&self, idx: usize, mut f: F) {
+        let (row, col) = ((idx / self.width) as isize, (idx % self.width) as isize);
+        for &(row_step, col_step) in &Self::DIRECTIONS {
+            let (mut r, mut c) = (row + row_step, col + col_step);
+            while r >= 0 && r < self.height as isize && c >= 0 && c < self.width as isize {
+                let neighbor = r as usize * self.width + c as usize;
+                if self.present[neighbor] {
+                    f(neighbor);
+                    break;
+                }
+                if self.neighborhood == GridNeighborhood::Adjacent {
+                    break;
+                }
+                r += row_step;
+                c += col_step;
+            }
+        }
+    }
+
+    fn cells(&self) -> Option<Vec<usize>> {
+        Some(
+            (0..self.present.len())
+                .filter(|&idx| self.present[idx])
+                .collect(),
+        )
+    }
+}
+
+/// An unbounded N dimensional square lattice where every cell neighbors the
+/// 3^N - 1 cells that differ by at most one in each coordinate.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Lattice<const N: usize>;
+
+impl<const N: usize> Lattice<N> {
+    /// The offset from a cell to each of its neighbors.
+    pub fn offsets() -> impl Iterator<Item = [isize; N]> {
+        let num_offsets = 3usize.pow(N as u32);
+        // Each offset is a base 3 number whose digits map 0, 1, 2 to -1, 0, 1.
+        // The middle offset is all ones, i.e. the cell itself.
+        let center = num_offsets / 2;
+        (0..num_offsets)
+            .filter(move |&offset| offset != center)
+            .map(|offset| {
+                let mut delta = [0; N];
+                let mut rem = offset;
+                for coord in delta.iter_mut() {
+                    *coord = (rem % 3) as isize - 1;
+                    rem /= 3;
+                }
+                delta
+            })
+    }
+}
+
+impl<const N: usize> Topology for Lattice<N> {
+    type Cell = [isize; N];
+
+    fn for_each_neighbor<F: FnMut([isize; N])>(&self, cell: [isize; N], mut f: F) {
+        for delta in Self::offsets() {
+            let mut neighbor = cell;
+            for (coord, d) in neighbor.iter_mut().zip(&delta) {
+                *coord += d;
+            }
+            f(neighbor);
+        }
+    }
+}
+
+/// An unbounded grid of hexagons in axial coordinates `(q, r)`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct HexGrid;
+
+impl Topology for HexGrid {
+    type Cell = (isize, isize);
+
+    fn for_each_neighbor<F: FnMut((isize, isize))>(&self, (q, r): (isize, isize), mut f: F) {
+        for &(dq, dr) in &[(1, 0), (1, -1), (0, -1), (-1, 0), (-1, 1), (0, 1)] {
+            f((q + dq, r + dr));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lattice_neighbors() {
+        let mut neighbors = Vec::new();
+        Lattice::<2>.for_each_neighbor([0, 0], |n| neighbors.push(n));
+        neighbors.sort();
+        assert_eq!(
+            neighbors,
+            vec![
+                [-1, -1],
+                [-1, 0],
+                [-1, 1],
+                [0, -1],
+                [0, 1],
+                [1, -1],
+                [1, 0],
+                [1, 1]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        // ..#
+        // ...
+        // #.#
+        let present = vec![false, false, true, false, false, false, true, false, true];
+        let mut adjacent = BoundedGrid::new(3, present.clone(), GridNeighborhood::Adjacent);
+        let mut neighbors = Vec::new();
+        adjacent.for_each_neighbor(6, |n| neighbors.push(n));
+        assert_eq!(neighbors, vec![]);
+
+        adjacent.neighborhood = GridNeighborhood::LineOfSight;
+        adjacent.for_each_neighbor(6, |n| neighbors.push(n));
+        neighbors.sort();
+        assert_eq!(neighbors, vec![2, 8]);
+    }
+
+    #[test]
+    fn test_run() {
+        let block = [[0, 0], [0, 1], [1, 0], [1, 1]];
+        let mut automaton = Automaton::new(Lattice::<2>, Rules::conway(), block.iter().copied());
+        assert_eq!(automaton.run(10), Outcome::Stable { generation: 0 });
+
+        let blinker = [[0, -1], [0, 0], [0, 1]];
+        let mut automaton = Automaton::new(Lattice::<2>, Rules::conway(), blinker.iter().copied());
+        assert_eq!(
+            automaton.run(10),
+            Outcome::Cycle {
+                start: 0,
+                period: 2
+            }
+        );
+        assert_eq!(automaton.population(), 3);
+
+        let glider = [[0, 1], [1, 2], [2, 0], [2, 1], [2, 2]];
+        let mut automaton = Automaton::new(Lattice::<2>, Rules::conway(), glider.iter().copied());
+        assert_eq!(automaton.run(10), Outcome::Running);
+        assert_eq!(automaton.generation(), 10);
+    }
+
+    #[test]
+    fn test_dense_matches_sparse() {
+        let layout = concat!(
+            "L.LL.LL.LL",
+            "LLLLLLL.LL",
+            "L.L.L..L..",
+            "LLLL.LL.LL",
+            "L.LL.LL.LL",
+            "L.LLLLL.LL",
+            "..L.L.....",
+            "LLLLLLLLLL",
+            "L.LLLLLL.L",
+            "L.LLLLL.LL",
+        );
+        let present: Vec<bool> = layout.bytes().map(|b| b == b'L').collect();
+        for &neighborhood in &[GridNeighborhood::Adjacent, GridNeighborhood::LineOfSight] {
+            let grid = BoundedGrid::new(10, present.clone(), neighborhood);
+            let rules = Rules::new([0], 0..4);
+            let mut sparse = Automaton::new(grid.clone(), rules, None::<usize>);
+            let mut dense = DenseAutomaton::new(&grid, rules, None::<usize>);
+            loop {
+                let mut live: Vec<_> = dense.live_cells().collect();
+                live.sort_unstable();
+                let mut expected: Vec<_> = sparse.live_cells().iter().copied().collect();
+                expected.sort_unstable();
+                assert_eq!(live, expected);
+                assert_eq!(dense.population(), sparse.population());
+                if !(sparse.step() | dense.step()) {
+                    break;
+                }
+            }
+        }
+    }
+}
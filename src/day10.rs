@@ -1,7 +1,9 @@
 use aoc_runner_derive::aoc;
 
+use std::fmt;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct Jolts(usize);
+pub struct Jolts(pub usize);
 impl std::ops::Add for Jolts {
     type Output = Jolts;
     fn add(self, b: Jolts) -> Jolts {
@@ -15,64 +17,351 @@ impl std::ops::Sub for Jolts {
     }
 }
 
+/// An unsigned integer of arbitrary size. Arrangement counts only ever need
+/// to be added together, so this stores base 10^9 limbs (least significant
+/// first) which keeps both addition and printing simple.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigUint(Vec<u32>);
+
+impl BigUint {
+    const BASE: u32 = 1_000_000_000;
+
+    pub fn zero() -> Self {
+        BigUint(Vec::new())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // The value divided by BASE^shift as a float. Used to compare the
+    // relative size of numbers that are too large to fit in an f64.
+    fn to_f64_shifted(&self, shift: usize) -> f64 {
+        self.0
+            .iter()
+            .enumerate()
+            .skip(shift)
+            .map(|(idx, &limb)| limb as f64 * (Self::BASE as f64).powi((idx - shift) as i32))
+            .sum()
+    }
+}
+
+impl From<u64> for BigUint {
+    fn from(mut value: u64) -> Self {
+        let mut limbs = Vec::new();
+        while value > 0 {
+            limbs.push((value % Self::BASE as u64) as u32);
+            value /= Self::BASE as u64;
+        }
+        BigUint(limbs)
+    }
+}
+
+impl std::ops::AddAssign<&BigUint> for BigUint {
+    fn add_assign(&mut self, b: &BigUint) {
+        if self.0.len() < b.0.len() {
+            self.0.resize(b.0.len(), 0);
+        }
+        let mut carry = 0;
+        for (idx, limb) in self.0.iter_mut().enumerate() {
+            let sum = *limb + b.0.get(idx).copied().unwrap_or(0) + carry;
+            *limb = sum % Self::BASE;
+            carry = sum / Self::BASE;
+        }
+        if carry > 0 {
+            self.0.push(carry);
+        }
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut limbs = self.0.iter().rev();
+        match limbs.next() {
+            None => write!(f, "0"),
+            Some(most_significant) => {
+                write!(f, "{}", most_significant)?;
+                for limb in limbs {
+                    write!(f, "{:09}", limb)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The constraints for connecting adapters together.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JoltageRules {
+    /// The largest difference in joltage allowed between connected adapters.
+    pub max_gap: usize,
+    /// How much higher the device's built-in adapter is than the highest
+    /// rated adapter.
+    pub device_offset: usize,
+}
+
+impl Default for JoltageRules {
+    fn default() -> Self {
+        JoltageRules {
+            max_gap: 3,
+            device_offset: 3,
+        }
+    }
+}
+
+/// The sorted joltage ratings from the charging outlet, through every
+/// adapter, to the device's built-in adapter.
+#[derive(Debug, Clone)]
+pub struct JoltageChain {
+    rules: JoltageRules,
+    jolts: Vec<Jolts>,
+    // ways_to_device[idx] is the number of arrangements that lead from
+    // jolts[idx] to the device.
+    ways_to_device: Vec<BigUint>,
+}
+
+impl JoltageChain {
+    pub fn new<I>(adapters: I, rules: JoltageRules) -> Self
+    where
+        I: IntoIterator<Item = Jolts>,
+    {
+        let mut jolts: Vec<Jolts> = std::iter::once(Jolts(0)).chain(adapters).collect();
+        jolts.sort();
+        jolts.push(*jolts.last().unwrap() + Jolts(rules.device_offset));
+
+        // Walk the adapters from the device back to the outlet. The number of
+        // arrangements from an adapter is the sum of the arrangements of every
+        // adapter it can connect to.
+        let mut ways_to_device = vec![BigUint::zero(); jolts.len()];
+        ways_to_device[jolts.len() - 1] = BigUint::from(1);
+        for idx in (0..jolts.len() - 1).rev() {
+            let mut ways = BigUint::zero();
+            for downstream_idx in idx + 1..jolts.len() {
+                if jolts[downstream_idx] - jolts[idx] > Jolts(rules.max_gap) {
+                    break;
+                }
+                ways += &ways_to_device[downstream_idx];
+            }
+            ways_to_device[idx] = ways;
+        }
+        JoltageChain {
+            rules,
+            jolts,
+            ways_to_device,
+        }
+    }
+
+    pub fn parse(input: &str, rules: JoltageRules) -> Option<Self> {
+        let adapters = input
+            .split('\n')
+            .map(|x| x.parse().ok().map(Jolts))
+            .collect::<Option<Vec<_>>>()?;
+        Some(JoltageChain::new(adapters, rules))
+    }
+
+    pub fn device_jolts(&self) -> Jolts {
+        *self.jolts.last().unwrap()
+    }
+
+    /// How many times each difference in joltage occurs when every adapter is
+    /// used, indexed by the difference. Returns `None` if any gap is larger
+    /// than the rules allow.
+    pub fn differences(&self) -> Option<Vec<usize>> {
+        let mut histogram = vec![0; self.rules.max_gap + 1];
+        for pair in self.jolts.windows(2) {
+            *histogram.get_mut((pair[1] - pair[0]).0)? += 1;
+        }
+        Some(histogram)
+    }
+
+    /// The number of distinct ways the adapters can connect the outlet to the
+    /// device.
+    pub fn arrangements(&self) -> BigUint {
+        self.ways_to_device[0].clone()
+    }
+
+    /// Lazily enumerate every valid arrangement in lexicographic order. Each
+    /// arrangement includes the outlet and the device.
+    pub fn arrangements_iter(&self) -> Arrangements<'_> {
+        Arrangements {
+            chain: self,
+            path: Vec::new(),
+            done: self.ways_to_device[0].is_zero(),
+        }
+    }
+
+    /// Pick a random valid arrangement, or `None` if there are none. `rng`
+    /// should return uniformly distributed `u64`s. Each connection is chosen
+    /// in proportion to the number of arrangements that follow it, so the
+    /// result is uniform over all arrangements up to floating point
+    /// precision.
+    pub fn sample_arrangement<R>(&self, mut rng: R) -> Option<Vec<Jolts>>
+    where
+        R: FnMut() -> u64,
+    {
+        if self.ways_to_device[0].is_zero() {
+            return None;
+        }
+        let mut idx = 0;
+        let mut arrangement = vec![self.jolts[0]];
+        while idx < self.jolts.len() - 1 {
+            let candidates = self.next_candidates(idx);
+            let shift = candidates
+                .clone()
+                .map(|c| self.ways_to_device[c].0.len())
+                .max()
+                .unwrap()
+                .saturating_sub(2);
+            let weights: Vec<(usize, f64)> = candidates
+                .map(|c| (c, self.ways_to_device[c].to_f64_shifted(shift)))
+                .collect();
+            let total: f64 = weights.iter().map(|&(_, w)| w).sum();
+            let mut choice = (rng() as f64 / u64::MAX as f64) * total;
+            idx = weights
+                .iter()
+                .find(|&&(_, w)| {
+                    choice -= w;
+                    w > 0.0 && choice <= 0.0
+                })
+                .or_else(|| weights.iter().rev().find(|&&(_, w)| w > 0.0))?
+                .0;
+            arrangement.push(self.jolts[idx]);
+        }
+        Some(arrangement)
+    }
+
+    // The indices of the adapters that can follow idx and still reach the
+    // device.
+    fn next_candidates(&self, idx: usize) -> impl Iterator<Item = usize> + Clone + '_ {
+        (idx + 1..self.jolts.len())
+            .take_while(move |&next| {
+                self.jolts[next] - self.jolts[idx] <= Jolts(self.rules.max_gap)
+            })
+            .filter(move |&next| !self.ways_to_device[next].is_zero())
+    }
+}
+
+/// Iterator over every valid arrangement of a `JoltageChain`.
+pub struct Arrangements<'a> {
+    chain: &'a JoltageChain,
+    // Indices into chain.jolts of the most recently returned arrangement.
+    path: Vec<usize>,
+    done: bool,
+}
+
+impl<'a> Arrangements<'a> {
+    // Extend the path with the lowest rated adapters until it reaches the
+    // device.
+    fn complete_path(&mut self) {
+        let mut idx = *self.path.last().unwrap();
+        while idx < self.chain.jolts.len() - 1 {
+            idx = self.chain.next_candidates(idx).next().unwrap();
+            self.path.push(idx);
+        }
+    }
+}
+
+impl<'a> Iterator for Arrangements<'a> {
+    type Item = Vec<Jolts>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.path.is_empty() {
+            self.path.push(0);
+            self.complete_path();
+        } else {
+            // Backtrack to the last adapter that has a higher rated
+            // alternative and take it.
+            loop {
+                let current = self.path.pop().unwrap();
+                let prev = match self.path.last() {
+                    Some(&prev) => prev,
+                    None => {
+                        self.done = true;
+                        return None;
+                    }
+                };
+                if let Some(alternative) = self
+                    .chain
+                    .next_candidates(prev)
+                    .find(|&next| next > current)
+                {
+                    self.path.push(alternative);
+                    break;
+                }
+            }
+            self.complete_path();
+        }
+        Some(self.path.iter().map(|&idx| self.chain.jolts[idx]).collect())
+    }
+}
+
 #[aoc(day10, part1)]
 pub fn solve_d10_p1(input: &str) -> usize {
-    let jolts = {
-        let mut jolts: Vec<Jolts> = std::iter::once(Jolts(0))
-            .chain(input.split('\n').map(|x| Jolts(x.parse().unwrap())))
-            .collect();
-        jolts.sort();
-        jolts.push(*jolts.last().unwrap() + Jolts(3)); // The devices built-in adapter is always 3 more than the highest.
-        jolts
-    };
-
-    // Count the number of 1-jolt differences and 3-jolt differences in the
-    // chain.
-    let (j1_diff, j3_diff) =
-        jolts
-            .windows(2)
-            .fold((0, 0), |(j1_diff, j3_diff), entry| match entry {
-                &[a, b] if b - a == Jolts(1) => (j1_diff + 1, j3_diff),
-                &[a, b] if b - a == Jolts(3) => (j1_diff, j3_diff + 1),
-                _ => panic!("fail"),
-            });
-    j1_diff * j3_diff
+    let chain = JoltageChain::parse(input, JoltageRules::default()).unwrap();
+    let differences = chain.differences().unwrap();
+    differences[1] * differences[3]
 }
 
 #[aoc(day10, part2)]
-pub fn solve_d10_p2(input: &str) -> usize {
-    let jolts = {
-        let mut jolts: Vec<Jolts> = std::iter::once(Jolts(0))
-            .chain(input.split('\n').map(|x| Jolts(x.parse().unwrap())))
-            .collect();
-        jolts.sort();
-        jolts.push(*jolts.last().unwrap() + Jolts(3)); // The devices built-in adapter is always 3 more than the highest.
-        jolts
-    };
-
-    // Iterate over the list of jolt value for the adapters. For each entry
-    // store the number of combinations this adapter has that lead back to zero
-    // in `combinations_count`
-    // To calculate the number of combinations the current adapter has you first
-    // determine which are the potential upstream adapters and then sum their
-    // combinations.
-
-    let mut combinations_count = Vec::with_capacity(jolts.len());
-    // The first entry in jolts is the `0` jolts. Initialize it with 1 to
-    // indicate that it is the only way to reach the end of the chain.
-    combinations_count.push(1);
-
-    // Skip the first entry in jolts since it's already initialized in
-    // combinations_count.
-    for (idx, current_jolts) in jolts.iter().copied().enumerate().skip(1) {
-        let mut current_combinations = 0;
-
-        for upstream_idx in idx.saturating_sub(3)..idx {
-            if current_jolts - jolts[upstream_idx] <= Jolts(3) {
-                current_combinations += combinations_count[upstream_idx];
-            }
+pub fn solve_d10_p2(input: &str) -> BigUint {
+    JoltageChain::parse(input, JoltageRules::default())
+        .unwrap()
+        .arrangements()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xorshift;
+
+    const EXAMPLE: &str = "16\n10\n15\n5\n1\n11\n7\n19\n6\n12\n4";
+
+    #[test]
+    fn test_chain() {
+        let chain = JoltageChain::parse(EXAMPLE, JoltageRules::default()).unwrap();
+        assert_eq!(chain.device_jolts(), Jolts(22));
+        assert_eq!(chain.differences(), Some(vec![0, 7, 0, 5]));
+        assert_eq!(chain.arrangements(), BigUint::from(8));
+
+        let arrangements: Vec<_> = chain.arrangements_iter().collect();
+        assert_eq!(arrangements.len(), 8);
+        for arrangement in &arrangements {
+            assert_eq!(arrangement.first(), Some(&Jolts(0)));
+            assert_eq!(arrangement.last(), Some(&Jolts(22)));
+            assert!(arrangement.windows(2).all(|w| w[1] - w[0] <= Jolts(3)));
         }
-        combinations_count.push(current_combinations);
+        assert!(arrangements.windows(2).all(|w| w[0] < w[1]));
+
+        let sample = chain
+            .sample_arrangement(xorshift(0x2545_f491_4f6c_dd1d))
+            .unwrap();
+        assert!(arrangements.contains(&sample));
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules = JoltageRules {
+            max_gap: 1,
+            device_offset: 1,
+        };
+        let chain = JoltageChain::parse("1\n2\n4", rules).unwrap();
+        assert_eq!(chain.differences(), None);
+        assert_eq!(chain.arrangements(), BigUint::zero());
+        assert_eq!(chain.arrangements_iter().next(), None);
+        assert_eq!(chain.sample_arrangement(|| 0), None);
+    }
+
+    #[test]
+    fn test_big_arrangements() {
+        let adapters = (1..=200).map(Jolts);
+        let chain = JoltageChain::new(adapters, JoltageRules::default());
+        // Far more arrangements than fit in a u128.
+        assert_eq!(
+            chain.arrangements().to_string(),
+            "52622583840983769603765180599790256716084480555530641"
+        );
     }
-    *combinations_count.last().unwrap()
 }
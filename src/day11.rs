@@ -1,9 +1,14 @@
+use crate::automaton::{BoundedGrid, DenseAutomaton, GridNeighborhood, Rules};
 use aoc_runner_derive::aoc;
 use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(u8)]
-enum GridSquare {
+pub enum GridSquare {
     Empty = b'L',
     Occupied = b'#',
     Floor = b'.',
@@ -33,31 +38,129 @@ impl fmt::Display for GridSquare {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-struct GridIdx(usize);
+/// A row major index into a `Grid`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GridIdx(pub usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GridError {
+    Empty,
+    /// A row doesn't have the same number of squares as the first row.
+    RaggedRow {
+        row: usize,
+        len: usize,
+        expected: usize,
+    },
+    InvalidSquare {
+        row: usize,
+        col: usize,
+        byte: u8,
+    },
+}
 
-#[derive(Debug, PartialEq, Eq)]
-struct Grid {
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GridError::Empty => write!(f, "grid is empty"),
+            GridError::RaggedRow { row, len, expected } => write!(
+                f,
+                "row {} has {} squares but the first row has {}",
+                row, len, expected
+            ),
+            GridError::InvalidSquare { row, col, byte } => write!(
+                f,
+                "invalid square {:?} at row {} column {}",
+                *byte as char, row, col
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GridError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
     row_len: usize,
     grid: Vec<GridSquare>,
 }
 
 impl Grid {
-    fn parse(input: &str) -> Option<Self> {
-        let row_len = input.find('\n')?;
-        let grid = input
-            .bytes()
-            .filter(|&b| b != b'\n')
-            .map(|b| GridSquare::from_u8(b))
-            .collect::<Option<Vec<_>>>()?;
-        Some(Grid { row_len, grid })
+    pub fn parse(input: &str) -> Result<Self, GridError> {
+        let mut row_len = None;
+        let mut grid = Vec::new();
+        for (row, line) in input.lines().enumerate() {
+            let expected = *row_len.get_or_insert(line.len());
+            if line.len() != expected {
+                return Err(GridError::RaggedRow {
+                    row,
+                    len: line.len(),
+                    expected,
+                });
+            }
+            for (col, byte) in line.bytes().enumerate() {
+                grid.push(GridSquare::from_u8(byte).ok_or(GridError::InvalidSquare {
+                    row,
+                    col,
+                    byte,
+                })?);
+            }
+        }
+        match row_len {
+            Some(row_len) if row_len > 0 => Ok(Grid { row_len, grid }),
+            _ => Err(GridError::Empty),
+        }
     }
 
-    fn new(grid: Vec<GridSquare>, row_len: usize) -> Grid {
-        if grid.len() % row_len != 0 {
-            panic!("invalid grid");
+    pub fn width(&self) -> usize {
+        self.row_len
+    }
+
+    pub fn height(&self) -> usize {
+        self.grid.len() / self.row_len
+    }
+
+    pub fn idx(&self, row: usize, col: usize) -> Option<GridIdx> {
+        if row < self.height() && col < self.width() {
+            Some(GridIdx(row * self.row_len + col))
+        } else {
+            None
+        }
+    }
+
+    /// The `(row, col)` coordinates of `idx`.
+    pub fn coords(&self, idx: GridIdx) -> (usize, usize) {
+        (idx.0 / self.row_len, idx.0 % self.row_len)
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<GridSquare> {
+        self.idx(row, col).map(|idx| self.grid[idx.0])
+    }
+
+    /// The number of occupied squares among the eight surrounding
+    /// `(row, col)`. Squares beyond the edges of the grid are skipped.
+    pub fn num_adjacent_occupied(&self, row: usize, col: usize) -> usize {
+        let mut total = 0;
+        for adj_row in row.saturating_sub(1)..=row + 1 {
+            for adj_col in col.saturating_sub(1)..=col + 1 {
+                if (adj_row, adj_col) != (row, col)
+                    && self.get(adj_row, adj_col) == Some(GridSquare::Occupied)
+                {
+                    total += 1;
+                }
+            }
         }
-        Grid { grid, row_len }
+        total
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[GridSquare]> + '_ {
+        self.grid.chunks_exact(self.row_len)
+    }
+
+    pub fn num_occupied(&self) -> usize {
+        self.grid
+            .iter()
+            .filter(|&&square| square == GridSquare::Occupied)
+            .count()
     }
 
     fn enumerate(&self) -> impl Iterator<Item = (GridIdx, GridSquare)> + '_ {
@@ -68,73 +171,78 @@ impl Grid {
             .map(|(idx, square)| (GridIdx(idx), square))
     }
 
-    fn is_occupied(&self, idx: usize) -> bool {
-        self.grid[idx] == GridSquare::Occupied
+    /// The seating layout as an automaton where every seat is a cell and the
+    /// occupied seats are alive. Each seat's neighbors are found once up
+    /// front, so line of sight rays aren't walked again every generation.
+    fn automaton(&self, neighborhood: GridNeighborhood, rules: Rules) -> DenseAutomaton<usize> {
+        let seats = self
+            .grid
+            .iter()
+            .map(|&square| square != GridSquare::Floor)
+            .collect();
+        let occupied = self
+            .enumerate()
+            .filter(|&(_idx, square)| square == GridSquare::Occupied)
+            .map(|(idx, _square)| idx.0);
+        DenseAutomaton::new(
+            &BoundedGrid::new(self.row_len, seats, neighborhood),
+            rules,
+            occupied,
+        )
     }
 
-    fn num_adjacent_occupied(&self, idx: GridIdx) -> usize {
-        let mut total = 0;
-        let col = idx.0 % self.row_len;
-
-        // Add row above idx if it exists.
-        if self.row_len < idx.0 {
-            let above_idx = idx.0 - self.row_len;
-            if col > 0 && self.is_occupied(above_idx - 1) {
-                total += 1;
-            }
-            if self.is_occupied(above_idx) {
-                total += 1;
-            }
-            if col < self.row_len - 1 && self.is_occupied(above_idx + 1) {
-                total += 1;
-            }
-        }
-
-        // Add left and right if they exist.
-        if col > 0 && self.is_occupied(idx.0 - 1) {
-            total += 1;
-        }
-        if col < self.row_len - 1 && self.is_occupied(idx.0 + 1) {
-            total += 1;
+    /// Every state the seating goes through, starting with this one and
+    /// ending with the first stable one. An empty seat is taken when no
+    /// neighboring seats are occupied, and an occupied seat is vacated when
+    /// `tolerance` or more neighbors are occupied.
+    pub fn generations(&self, neighborhood: GridNeighborhood, tolerance: usize) -> Generations {
+        Generations {
+            automaton: self.automaton(neighborhood, Rules::new([0], 0..tolerance)),
+            layout: self.clone(),
+            started: false,
         }
+    }
 
-        // Add row below if it exists.
-        let below_idx = idx.0 + self.row_len;
-        if below_idx < self.grid.len() {
-            if col > 0 && self.is_occupied(below_idx - 1) {
-                total += 1;
-            }
-            if self.is_occupied(below_idx) {
-                total += 1;
+    /// Write the grid as a binary PPM or PGM image, drawing each square as a
+    /// `scale` by `scale` block of pixels.
+    pub fn write_image<W: Write>(
+        &self,
+        out: &mut W,
+        format: ImageFormat,
+        scale: usize,
+    ) -> io::Result<()> {
+        let magic = match format {
+            ImageFormat::Ppm => "P6",
+            ImageFormat::Pgm => "P5",
+        };
+        write!(
+            out,
+            "{}\n{} {}\n255\n",
+            magic,
+            self.width() * scale,
+            self.height() * scale
+        )?;
+        let mut line = Vec::new();
+        for row in self.rows() {
+            line.clear();
+            for &square in row {
+                let pixel: &[u8] = match (format, square) {
+                    (ImageFormat::Ppm, GridSquare::Floor) => &[40, 40, 40],
+                    (ImageFormat::Ppm, GridSquare::Empty) => &[40, 200, 40],
+                    (ImageFormat::Ppm, GridSquare::Occupied) => &[220, 40, 40],
+                    (ImageFormat::Pgm, GridSquare::Floor) => &[0],
+                    (ImageFormat::Pgm, GridSquare::Empty) => &[128],
+                    (ImageFormat::Pgm, GridSquare::Occupied) => &[255],
+                };
+                for _ in 0..scale {
+                    line.extend_from_slice(pixel);
+                }
             }
-            if col < self.row_len - 1 && self.is_occupied(below_idx + 1) {
-                total += 1;
+            for _ in 0..scale {
+                out.write_all(&line)?;
             }
         }
-        total
-    }
-
-    fn num_visible_occupied(&self, idx: GridIdx) -> usize {
-        let mut total = 0;
-        for &(x_step, y_step) in &[
-            (-1, -1),
-            (-1, 0),
-            (-1, 1),
-            (0, -1),
-            (0, 1),
-            (1, -1),
-            (1, 0),
-            (1, 1),
-        ] {
-            total += GridIter::new(self, idx, x_step, y_step)
-                .find_map(|idx| match &self.grid[idx.0] {
-                    GridSquare::Empty => Some(0),
-                    GridSquare::Occupied => Some(1),
-                    GridSquare::Floor => None,
-                })
-                .unwrap_or(0);
-        }
-        total
+        Ok(())
     }
 }
 
@@ -150,126 +258,270 @@ impl fmt::Display for Grid {
     }
 }
 
-#[aoc(day11, part1)]
-fn solve_d11_p1(input: &str) -> usize {
-    fn next_grid(orig: &Grid) -> Grid {
-        let new_grid: Vec<GridSquare> = orig
+/// Iterator over successive seating states. See `Grid::generations`.
+pub struct Generations {
+    automaton: DenseAutomaton<usize>,
+    layout: Grid,
+    started: bool,
+}
+
+impl Iterator for Generations {
+    type Item = Grid;
+
+    fn next(&mut self) -> Option<Grid> {
+        if self.started && !self.automaton.step() {
+            return None;
+        }
+        self.started = true;
+        let grid = self
+            .layout
+            .grid
+            .iter()
             .enumerate()
-            .map(|(grid_idx, grid_square)| match grid_square {
-                GridSquare::Empty => {
-                    let occupied = orig.num_adjacent_occupied(grid_idx);
-                    if occupied == 0 {
-                        GridSquare::Occupied
-                    } else {
-                        GridSquare::Empty
-                    }
-                }
-                GridSquare::Occupied => {
-                    let occupied = orig.num_adjacent_occupied(grid_idx);
-                    if occupied >= 4 {
-                        GridSquare::Empty
-                    } else {
-                        GridSquare::Occupied
-                    }
-                }
+            .map(|(idx, &square)| match square {
                 GridSquare::Floor => GridSquare::Floor,
+                _ if self.automaton.is_alive(idx) => GridSquare::Occupied,
+                _ => GridSquare::Empty,
             })
             .collect();
-        Grid::new(new_grid, orig.row_len)
+        Some(Grid {
+            row_len: self.layout.row_len,
+            grid,
+        })
     }
-    let mut grid = Grid::parse(input).unwrap();
-    let stable_grid = loop {
-        let next = next_grid(&grid);
-        if next == grid {
-            break next;
-        }
-        grid = next;
-    };
-    stable_grid
-        .enumerate()
-        .filter(|&(_idx, square)| square == GridSquare::Occupied)
-        .count()
 }
 
-#[derive(Debug)]
-struct GridIter {
-    x: isize,
-    y: isize,
-    x_step: isize,
-    y_step: isize,
-    num_cols: usize,
-    num_rows: usize,
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Binary RGB pixmap.
+    Ppm,
+    /// Binary grayscale graymap.
+    Pgm,
 }
-impl GridIter {
-    fn new(grid: &Grid, idx: GridIdx, x_step: isize, y_step: isize) -> Self {
-        let num_cols = grid.row_len;
-        let num_rows = grid.grid.len() / num_cols;
-        let x = (idx.0 % num_cols) as isize;
-        let y = (idx.0 / num_cols) as isize;
-        GridIter {
-            x,
-            y,
-            x_step,
-            y_step,
-            num_cols,
-            num_rows,
+
+/// Play the generations back as a terminal animation, clearing the screen
+/// before each frame and pausing `frame_delay` between frames. Occupied seats
+/// are drawn in red, empty seats in green and floor dimmed.
+pub fn write_ansi_animation<W, I>(
+    out: &mut W,
+    generations: I,
+    frame_delay: Duration,
+) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = Grid>,
+{
+    for (generation, grid) in generations.into_iter().enumerate() {
+        if generation > 0 && frame_delay > Duration::from_secs(0) {
+            std::thread::sleep(frame_delay);
         }
+        write!(out, "\x1b[H\x1b[2J")?;
+        writeln!(
+            out,
+            "generation {}: {} occupied",
+            generation,
+            grid.num_occupied()
+        )?;
+        for row in grid.rows() {
+            for &square in row {
+                match square {
+                    // Each square resets the attributes left by the last.
+                    GridSquare::Floor => write!(out, "\x1b[0;2m.")?,
+                    GridSquare::Empty => write!(out, "\x1b[0;32mL")?,
+                    GridSquare::Occupied => write!(out, "\x1b[0;31m#")?,
+                }
+            }
+            writeln!(out, "\x1b[0m")?;
+        }
+        out.flush()?;
     }
+    Ok(())
 }
 
-impl Iterator for GridIter {
-    type Item = GridIdx;
-
-    fn next(&mut self) -> Option<GridIdx> {
-        self.x += self.x_step;
-        self.y += self.y_step;
-        if self.x < 0 || self.x >= self.num_cols as isize {
-            return None;
-        }
-        if self.y < 0 || self.y >= self.num_rows as isize {
-            return None;
-        }
-        Some(GridIdx(self.y as usize * self.num_cols + self.x as usize))
+/// Write each generation as a numbered image (`frame_0000.ppm`, ...) in `dir`,
+/// creating it if needed. Returns the number of frames written.
+pub fn write_image_frames<P, I>(
+    dir: P,
+    generations: I,
+    format: ImageFormat,
+    scale: usize,
+) -> io::Result<usize>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = Grid>,
+{
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let extension = match format {
+        ImageFormat::Ppm => "ppm",
+        ImageFormat::Pgm => "pgm",
+    };
+    let mut frames = 0;
+    for grid in generations {
+        let path = dir.join(format!("frame_{:04}.{}", frames, extension));
+        let mut out = BufWriter::new(File::create(path)?);
+        grid.write_image(&mut out, format, scale)?;
+        out.flush()?;
+        frames += 1;
     }
+    Ok(frames)
+}
+
+/// Let people shuffle seats until nobody moves and count the occupied seats.
+/// An empty seat is taken when no neighboring seats are occupied, and an
+/// occupied seat is vacated when `tolerance` or more neighbors are occupied.
+fn stable_occupied(input: &str, neighborhood: GridNeighborhood, tolerance: usize) -> usize {
+    let grid = Grid::parse(input).unwrap();
+    let mut automaton = grid.automaton(neighborhood, Rules::new([0], 0..tolerance));
+    automaton
+        .run_until_stable(usize::MAX)
+        .expect("seating never stabilized");
+    automaton.population()
+}
+
+#[aoc(day11, part1)]
+fn solve_d11_p1(input: &str) -> usize {
+    stable_occupied(input, GridNeighborhood::Adjacent, 4)
 }
 
 #[aoc(day11, part2)]
 fn solve_d11_p2(input: &str) -> usize {
-    fn next_grid(orig: &Grid) -> Grid {
-        let new_grid: Vec<GridSquare> = orig
-            .enumerate()
-            .map(|(grid_idx, grid_square)| match grid_square {
-                GridSquare::Empty => {
-                    let occupied = orig.num_visible_occupied(grid_idx);
-                    if occupied == 0 {
-                        GridSquare::Occupied
-                    } else {
-                        GridSquare::Empty
-                    }
-                }
-                GridSquare::Occupied => {
-                    let occupied = orig.num_visible_occupied(grid_idx);
-                    if occupied >= 5 {
-                        GridSquare::Empty
-                    } else {
-                        GridSquare::Occupied
-                    }
-                }
-                GridSquare::Floor => GridSquare::Floor,
+    stable_occupied(input, GridNeighborhood::LineOfSight, 5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xorshift;
+
+    const EXAMPLE: &str = r#"L.LL.LL.LL
+LLLLLLL.LL
+L.L.L..L..
+LLLL.LL.LL
+L.LL.LL.LL
+L.LLLLL.LL
+..L.L.....
+LLLLLLLLLL
+L.LLLLLL.L
+L.LLLLL.LL"#;
+
+    #[test]
+    fn test_generations() {
+        let grid = Grid::parse(EXAMPLE).unwrap();
+        let generations: Vec<_> = grid.generations(GridNeighborhood::Adjacent, 4).collect();
+        assert_eq!(generations.len(), 6);
+        assert_eq!(generations[0], grid);
+        assert_eq!(generations[1].num_occupied(), 71);
+        assert_eq!(generations[5].num_occupied(), 37);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Grid::parse(""), Err(GridError::Empty));
+        assert_eq!(
+            Grid::parse("L.L\n#L\nLLL"),
+            Err(GridError::RaggedRow {
+                row: 1,
+                len: 2,
+                expected: 3
             })
-            .collect();
-        Grid::new(new_grid, orig.row_len)
+        );
+        assert_eq!(
+            Grid::parse("L.L\nL?L"),
+            Err(GridError::InvalidSquare {
+                row: 1,
+                col: 1,
+                byte: b'?'
+            })
+        );
+        let grid = Grid::parse("L#L").unwrap();
+        assert_eq!((grid.width(), grid.height()), (3, 1));
+        assert_eq!(grid.get(0, 1), Some(GridSquare::Occupied));
+        assert_eq!(grid.get(1, 0), None);
+        assert_eq!(grid.coords(grid.idx(0, 2).unwrap()), (0, 2));
     }
-    let mut grid = Grid::parse(input).unwrap();
-    let stable_grid = loop {
-        let next = next_grid(&grid);
-        if next == grid {
-            break next;
+
+    // Random grids from a fixed xorshift sequence.
+    fn random_grids() -> impl Iterator<Item = Grid> {
+        let mut next = xorshift(0x9e37_79b9_7f4a_7c15);
+        (0..200).map(move |_| {
+            let width = 1 + (next() % 12) as usize;
+            let height = 1 + (next() % 12) as usize;
+            let rows: Vec<String> = (0..height)
+                .map(|_| {
+                    (0..width)
+                        .map(|_| ['L', '#', '.'][(next() % 3) as usize])
+                        .collect()
+                })
+                .collect();
+            Grid::parse(&rows.join("\n")).unwrap()
+        })
+    }
+
+    // Check every square of the grid against its position.
+    fn naive_adjacent_occupied(grid: &Grid, row: usize, col: usize) -> usize {
+        grid.enumerate()
+            .filter(|&(idx, square)| {
+                let (r, c) = grid.coords(idx);
+                let distance = std::cmp::max(
+                    (r as isize - row as isize).abs(),
+                    (c as isize - col as isize).abs(),
+                );
+                distance == 1 && square == GridSquare::Occupied
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_adjacent_occupied_matches_naive() {
+        for grid in random_grids() {
+            let automaton = grid.automaton(GridNeighborhood::Adjacent, Rules::new([0], 0..4));
+            for (idx, square) in grid.enumerate() {
+                let (row, col) = grid.coords(idx);
+                let expected = naive_adjacent_occupied(&grid, row, col);
+                assert_eq!(grid.num_adjacent_occupied(row, col), expected, "{}", grid);
+                if square != GridSquare::Floor {
+                    let from_automaton = automaton
+                        .neighbors(idx.0)
+                        .unwrap()
+                        .filter(|&n| grid.grid[n] == GridSquare::Occupied)
+                        .count();
+                    assert_eq!(from_automaton, expected, "{}", grid);
+                }
+            }
         }
-        grid = next;
-    };
-    stable_grid
-        .enumerate()
-        .filter(|&(_idx, square)| square == GridSquare::Occupied)
-        .count()
+    }
+
+    #[test]
+    fn test_write_image() {
+        let grid = Grid::parse("L.\n#L").unwrap();
+        let mut pgm = Vec::new();
+        grid.write_image(&mut pgm, ImageFormat::Pgm, 2).unwrap();
+        let mut expected = b"P5\n4 4\n255\n".to_vec();
+        expected.extend_from_slice(&[128, 128, 0, 0, 128, 128, 0, 0]);
+        expected.extend_from_slice(&[255, 255, 128, 128, 255, 255, 128, 128]);
+        assert_eq!(pgm, expected);
+    }
+
+    #[test]
+    fn test_write_animation() {
+        let grid = Grid::parse("L.#").unwrap();
+        let mut ansi = Vec::new();
+        write_ansi_animation(&mut ansi, vec![grid], Duration::from_secs(0)).unwrap();
+        assert_eq!(
+            String::from_utf8(ansi).unwrap(),
+            "\x1b[H\x1b[2Jgeneration 0: 1 occupied\n\
+             \x1b[0;32mL\x1b[0;2m.\x1b[0;31m#\x1b[0m\n"
+        );
+
+        let dir = std::env::temp_dir().join(format!("aoc_day11_frames_{}", std::process::id()));
+        let grids = vec![Grid::parse("L.#").unwrap(), Grid::parse("#.L").unwrap()];
+        let frames = write_image_frames(&dir, grids, ImageFormat::Ppm, 2).unwrap();
+        assert_eq!(frames, 2);
+        let first = fs::read(dir.join("frame_0000.ppm")).unwrap();
+        assert!(first.starts_with(b"P6\n6 2\n255\n"));
+        assert!(dir.join("frame_0001.ppm").exists());
+        assert!(!dir.join("frame_0002.ppm").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
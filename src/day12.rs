@@ -1,51 +1,16 @@
 use aoc_runner_derive::aoc;
 
+use std::convert::TryFrom;
+use std::fmt;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-enum LeftRight {
+pub enum LeftRight {
     Left,
     Right,
 }
 
-#[derive(Debug, Copy, Clone)]
-#[repr(u8)]
-enum Direction {
-    North = 0,
-    East = 1,
-    South = 2,
-    West = 3,
-}
-
-impl Direction {
-    fn xy(self) -> (isize, isize) {
-        match self {
-            Direction::North => (0, 1),
-            Direction::East => (1, 0),
-            Direction::South => (0, -1),
-            Direction::West => (-1, 0),
-        }
-    }
-
-    fn from_usize(x: usize) -> Self {
-        match x {
-            0 => Direction::North,
-            1 => Direction::East,
-            2 => Direction::South,
-            3 => Direction::West,
-            _ => panic!("invalid direction"),
-        }
-    }
-
-    fn rotate_ship(self, degrees: usize, lr: LeftRight) -> Self {
-        debug_assert!(degrees % 90 == 0);
-        let num_turns = degrees / 90;
-        Direction::from_usize(match lr {
-            LeftRight::Left => (self as usize).wrapping_sub(num_turns) & 0b11,
-            LeftRight::Right => (self as usize).wrapping_add(num_turns) & 0b11,
-        })
-    }
-}
-
-enum Action {
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
     North(usize),
     East(usize),
     South(usize),
@@ -56,8 +21,8 @@ enum Action {
 }
 
 impl Action {
-    fn parse(input: &str) -> Option<Action> {
-        let value = (&input[1..]).parse().ok()?;
+    pub fn parse(input: &str) -> Option<Action> {
+        let value = input.get(1..)?.parse().ok()?;
         Some(match input.as_bytes()[0] {
             b'N' => Action::North(value),
             b'E' => Action::East(value),
@@ -71,78 +36,690 @@ impl Action {
     }
 }
 
-#[aoc(day12, part1)]
-fn solve_d12_p1(input: &str) -> usize {
-    let mut x = 0isize;
-    let mut y = 0isize;
-    let mut ship_dir = Direction::East;
-    for action in input.split('\n').map(|x| Action::parse(x).unwrap()) {
-        match action {
-            Action::North(distance) => y += distance as isize,
-            Action::East(distance) => x += distance as isize,
-            Action::South(distance) => y -= distance as isize,
-            Action::West(distance) => x -= distance as isize,
-            Action::Left(degrees) => ship_dir = ship_dir.rotate_ship(degrees, LeftRight::Left),
-            Action::Right(degrees) => ship_dir = ship_dir.rotate_ship(degrees, LeftRight::Right),
-            Action::Forward(distance) => {
-                let (x_step, y_step) = ship_dir.xy();
-                x += x_step * distance as isize;
-                y += y_step * distance as isize;
+/// A coordinate type for positions and directions. Integer coordinates only
+/// support turns by multiples of 90 degrees and are exact; floating point
+/// coordinates also support turns by any angle.
+pub trait Coord: Copy + PartialEq + PartialOrd + fmt::Debug {
+    /// A manhattan distance, wide enough that it can't overflow.
+    type Distance: Copy + PartialOrd + fmt::Debug;
+
+    fn from_i32(value: i32) -> Self;
+    fn from_distance(distance: usize) -> Option<Self>;
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    fn checked_sub(self, rhs: Self) -> Option<Self>;
+    fn checked_mul(self, rhs: Self) -> Option<Self>;
+    fn checked_neg(self) -> Option<Self>;
+    fn manhattan(x: Self, y: Self) -> Self::Distance;
+    fn to_f64(self) -> f64;
+
+    /// Rotate by an angle that isn't a multiple of 90 degrees, if
+    /// supported.
+    fn rotate_any(v: Vector<Self>, degrees: usize, lr: LeftRight) -> Option<Vector<Self>>;
+}
+
+impl Coord for i64 {
+    type Distance = u128;
+
+    fn from_i32(value: i32) -> Self {
+        value.into()
+    }
+
+    fn from_distance(distance: usize) -> Option<Self> {
+        i64::try_from(distance).ok()
+    }
+
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        i64::checked_add(self, rhs)
+    }
+
+    fn checked_sub(self, rhs: Self) -> Option<Self> {
+        i64::checked_sub(self, rhs)
+    }
+
+    fn checked_mul(self, rhs: Self) -> Option<Self> {
+        i64::checked_mul(self, rhs)
+    }
+
+    fn checked_neg(self) -> Option<Self> {
+        i64::checked_neg(self)
+    }
+
+    fn manhattan(x: Self, y: Self) -> u128 {
+        x.unsigned_abs() as u128 + y.unsigned_abs() as u128
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn rotate_any(_: Vector<Self>, _: usize, _: LeftRight) -> Option<Vector<Self>> {
+        None
+    }
+}
+
+// Floating point results are only rejected if they aren't finite.
+fn finite(value: f64) -> Option<f64> {
+    if value.is_finite() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+impl Coord for f64 {
+    type Distance = f64;
+
+    fn from_i32(value: i32) -> Self {
+        value.into()
+    }
+
+    fn from_distance(distance: usize) -> Option<Self> {
+        Some(distance as f64)
+    }
+
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        finite(self + rhs)
+    }
+
+    fn checked_sub(self, rhs: Self) -> Option<Self> {
+        finite(self - rhs)
+    }
+
+    fn checked_mul(self, rhs: Self) -> Option<Self> {
+        finite(self * rhs)
+    }
+
+    fn checked_neg(self) -> Option<Self> {
+        Some(-self)
+    }
+
+    fn manhattan(x: Self, y: Self) -> f64 {
+        x.abs() + y.abs()
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn rotate_any(v: Vector<Self>, degrees: usize, lr: LeftRight) -> Option<Vector<Self>> {
+        let radians = match lr {
+            LeftRight::Left => (degrees % 360) as f64,
+            LeftRight::Right => -((degrees % 360) as f64),
+        }
+        .to_radians();
+        let (sin, cos) = radians.sin_cos();
+        Some(Vector::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NavigationError {
+    /// The coordinate type only supports multiples of 90 degrees.
+    UnsupportedTurn { degrees: usize },
+    /// A position or direction no longer fits in the coordinate type.
+    Overflow,
+}
+
+impl fmt::Display for NavigationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NavigationError::UnsupportedTurn { degrees } => {
+                write!(f, "cannot turn {} degrees with whole coordinates", degrees)
             }
+            NavigationError::Overflow => write!(f, "coordinate overflow"),
         }
     }
-    x.abs() as usize + y.abs() as usize
 }
 
-#[derive(Debug, Copy, Clone)]
-struct Waypoint {
-    x: isize,
-    y: isize,
+impl std::error::Error for NavigationError {}
+
+/// A position or direction with east as +x and north as +y.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vector<T> {
+    pub x: T,
+    pub y: T,
 }
 
-impl Waypoint {
-    fn rotate(self, mut degrees: usize, lr: LeftRight) -> Self {
-        assert!(degrees == 90 || degrees == 180 || degrees == 270);
-        if lr == LeftRight::Left {
-            degrees = 360 - degrees;
+impl<T: Coord> Vector<T> {
+    pub fn new(x: T, y: T) -> Self {
+        Vector { x, y }
+    }
+
+    pub fn manhattan_distance(self) -> T::Distance {
+        T::manhattan(self.x, self.y)
+    }
+
+    fn to_f64(self) -> Vector<f64> {
+        Vector::new(self.x.to_f64(), self.y.to_f64())
+    }
+
+    // Move by a compass action, or return `None` for other actions.
+    fn compass_move(self, action: Action) -> Option<Result<Self, NavigationError>> {
+        let (x, y) = (self.x, self.y);
+        let moved = match action {
+            Action::North(distance) => T::from_distance(distance)
+                .and_then(|d| y.checked_add(d))
+                .map(|y| Vector::new(x, y)),
+            Action::East(distance) => T::from_distance(distance)
+                .and_then(|d| x.checked_add(d))
+                .map(|x| Vector::new(x, y)),
+            Action::South(distance) => T::from_distance(distance)
+                .and_then(|d| y.checked_sub(d))
+                .map(|y| Vector::new(x, y)),
+            Action::West(distance) => T::from_distance(distance)
+                .and_then(|d| x.checked_sub(d))
+                .map(|x| Vector::new(x, y)),
+            _ => return None,
+        };
+        Some(moved.ok_or(NavigationError::Overflow))
+    }
+
+    fn add_scaled(self, v: Self, scale: usize) -> Result<Self, NavigationError> {
+        let scaled = |position: T, step: T| {
+            position.checked_add(step.checked_mul(T::from_distance(scale)?)?)
+        };
+        match (scaled(self.x, v.x), scaled(self.y, v.y)) {
+            (Some(x), Some(y)) => Ok(Vector::new(x, y)),
+            _ => Err(NavigationError::Overflow),
+        }
+    }
+
+    /// Rotate around the origin. Multiples of 90 degrees are handled exactly
+    /// by swapping and negating components.
+    pub fn rotate(self, degrees: usize, lr: LeftRight) -> Result<Self, NavigationError> {
+        if !degrees.is_multiple_of(90) {
+            return T::rotate_any(self, degrees, lr)
+                .ok_or(NavigationError::UnsupportedTurn { degrees });
         }
-        match degrees {
-            0 => self,
-            90 => Waypoint {
-                x: self.y,
-                y: -self.x,
-            },
-            180 => Waypoint {
-                x: -self.x,
-                y: -self.y,
-            },
-            270 => Waypoint {
-                x: -self.y,
-                y: self.x,
-            },
+        let clockwise_turns = match lr {
+            LeftRight::Left => 4 - (degrees / 90) % 4,
+            LeftRight::Right => (degrees / 90) % 4,
+        };
+        let rotated = match clockwise_turns % 4 {
+            0 => Some(self),
+            1 => self.x.checked_neg().map(|x| Vector::new(self.y, x)),
+            2 => self
+                .x
+                .checked_neg()
+                .and_then(|x| Some(Vector::new(x, self.y.checked_neg()?))),
+            _ => self.y.checked_neg().map(|y| Vector::new(y, self.x)),
+        };
+        rotated.ok_or(NavigationError::Overflow)
+    }
+}
+
+/// A strategy for moving a ship in response to navigation actions. Rejected
+/// actions leave the strategy and position unchanged.
+pub trait Navigation {
+    type Coord: Coord;
+
+    fn apply(
+        &mut self,
+        position: &mut Vector<Self::Coord>,
+        action: Action,
+    ) -> Result<(), NavigationError>;
+
+    /// The waypoint's position relative to the ship, if this strategy uses
+    /// one.
+    fn waypoint(&self) -> Option<Vector<Self::Coord>> {
+        None
+    }
+}
+
+/// The compass actions move the ship directly and turns change its heading.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Direct<T> {
+    /// A unit vector in the direction the ship faces.
+    pub heading: Vector<T>,
+}
+
+impl<T: Coord> Default for Direct<T> {
+    /// Facing east.
+    fn default() -> Self {
+        Direct {
+            heading: Vector::new(T::from_i32(1), T::from_i32(0)),
+        }
+    }
+}
+
+impl<T: Coord> Navigation for Direct<T> {
+    type Coord = T;
+
+    fn apply(&mut self, position: &mut Vector<T>, action: Action) -> Result<(), NavigationError> {
+        if let Some(moved) = position.compass_move(action) {
+            *position = moved?;
+            return Ok(());
+        }
+        match action {
+            Action::Left(degrees) => {
+                self.heading = self.heading.rotate(degrees, LeftRight::Left)?
+            }
+            Action::Right(degrees) => {
+                self.heading = self.heading.rotate(degrees, LeftRight::Right)?
+            }
+            Action::Forward(distance) => *position = position.add_scaled(self.heading, distance)?,
             _ => unreachable!(),
         }
+        Ok(())
     }
 }
 
-#[aoc(day12, part2)]
-fn solve_d12_p2(input: &str) -> usize {
-    let mut waypoint = Waypoint { x: 10, y: 1 };
-    let mut x = 0isize;
-    let mut y = 0isize;
-    for action in input.split('\n').map(|x| Action::parse(x).unwrap()) {
+/// The compass actions move a waypoint relative to the ship, turns rotate the
+/// waypoint around the ship, and the ship moves towards the waypoint.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Waypoint<T> {
+    /// The waypoint's position relative to the ship.
+    pub offset: Vector<T>,
+}
+
+impl<T: Coord> Default for Waypoint<T> {
+    /// 10 units east and 1 unit north of the ship.
+    fn default() -> Self {
+        Waypoint {
+            offset: Vector::new(T::from_i32(10), T::from_i32(1)),
+        }
+    }
+}
+
+impl<T: Coord> Navigation for Waypoint<T> {
+    type Coord = T;
+
+    fn apply(&mut self, position: &mut Vector<T>, action: Action) -> Result<(), NavigationError> {
+        if let Some(moved) = self.offset.compass_move(action) {
+            self.offset = moved?;
+            return Ok(());
+        }
         match action {
-            Action::North(distance) => waypoint.y += distance as isize,
-            Action::East(distance) => waypoint.x += distance as isize,
-            Action::South(distance) => waypoint.y -= distance as isize,
-            Action::West(distance) => waypoint.x -= distance as isize,
-            Action::Left(degrees) => waypoint = waypoint.rotate(degrees, LeftRight::Left),
-            Action::Right(degrees) => waypoint = waypoint.rotate(degrees, LeftRight::Right),
-            Action::Forward(distance) => {
-                x += waypoint.x * distance as isize;
-                y += waypoint.y * distance as isize;
+            Action::Left(degrees) => self.offset = self.offset.rotate(degrees, LeftRight::Left)?,
+            Action::Right(degrees) => {
+                self.offset = self.offset.rotate(degrees, LeftRight::Right)?
             }
+            Action::Forward(distance) => *position = position.add_scaled(self.offset, distance)?,
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn waypoint(&self) -> Option<Vector<T>> {
+        Some(self.offset)
+    }
+}
+
+/// Where the ship and its waypoint were after an action.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrackPoint<T> {
+    /// The action that led here, or `None` for the starting point.
+    pub action: Option<Action>,
+    pub position: Vector<T>,
+    /// The waypoint's position relative to the ship, if there is one.
+    pub waypoint: Option<Vector<T>>,
+}
+
+/// Summary of a ship's route.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrackStats<T> {
+    /// The euclidean length of the path the ship followed.
+    pub distance_traveled: f64,
+    /// The south west and north east corners of the box containing every
+    /// position the ship visited.
+    pub min: Vector<T>,
+    pub max: Vector<T>,
+    /// The position with the greatest manhattan distance from the origin.
+    pub farthest: Vector<T>,
+}
+
+/// Every position a ship has been at, starting at the origin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track<T> {
+    points: Vec<TrackPoint<T>>,
+}
+
+fn lesser<T: PartialOrd>(a: T, b: T) -> T {
+    if b < a {
+        b
+    } else {
+        a
+    }
+}
+
+fn greater<T: PartialOrd>(a: T, b: T) -> T {
+    if b > a {
+        b
+    } else {
+        a
+    }
+}
+
+impl<T: Coord> Track<T> {
+    pub fn points(&self) -> &[TrackPoint<T>] {
+        &self.points
+    }
+
+    pub fn stats(&self) -> TrackStats<T> {
+        let start = self.points[0].position;
+        let mut stats = TrackStats {
+            distance_traveled: 0.0,
+            min: start,
+            max: start,
+            farthest: start,
+        };
+        for pair in self.points.windows(2) {
+            let (a, b) = (pair[0].position.to_f64(), pair[1].position.to_f64());
+            stats.distance_traveled += (b.x - a.x).hypot(b.y - a.y);
+            let b = pair[1].position;
+            stats.min = Vector::new(lesser(stats.min.x, b.x), lesser(stats.min.y, b.y));
+            stats.max = Vector::new(greater(stats.max.x, b.x), greater(stats.max.y, b.y));
+            if b.manhattan_distance() > stats.farthest.manhattan_distance() {
+                stats.farthest = b;
+            }
+        }
+        stats
+    }
+
+    /// Draw the route as an SVG image with north at the top. The start is
+    /// marked in green, the end in red, and the final waypoint is drawn as
+    /// an arrow from the ship along with the dashed path it followed.
+    pub fn write_svg<W: std::io::Write>(&self, mut out: W) -> std::io::Result<()> {
+        // Drawing doesn't need to be exact, so everything is converted to
+        // floating point and waypoints are made absolute.
+        let positions: Vec<Vector<f64>> = self.points.iter().map(|p| p.position.to_f64()).collect();
+        let waypoints: Vec<Vector<f64>> = self
+            .points
+            .iter()
+            .filter_map(|p| {
+                let (position, offset) = (p.position.to_f64(), p.waypoint?.to_f64());
+                Some(Vector::new(position.x + offset.x, position.y + offset.y))
+            })
+            .collect();
+        let mut min = positions[0];
+        let mut max = min;
+        for v in positions.iter().chain(&waypoints) {
+            min = Vector::new(min.x.min(v.x), min.y.min(v.y));
+            max = Vector::new(max.x.max(v.x), max.y.max(v.y));
+        }
+        let size = (max.x - min.x).max(max.y - min.y).max(1.0);
+        let margin = size * 0.05;
+        let stroke = size / 500.0;
+        // SVG's y axis points down, so every y coordinate is negated.
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+            min.x - margin,
+            -max.y - margin,
+            max.x - min.x + 2.0 * margin,
+            max.y - min.y + 2.0 * margin
+        )?;
+        writeln!(
+            out,
+            r#"<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="6" markerHeight="6" orient="auto"><path d="M0,0 L10,5 L0,10 z" fill="orange"/></marker></defs>"#
+        )?;
+        let polyline = |out: &mut W, points: &mut dyn Iterator<Item = Vector<f64>>, style: &str| {
+            write!(out, r#"<polyline fill="none" {} points=""#, style)?;
+            for (idx, v) in points.enumerate() {
+                let sep = if idx == 0 { "" } else { " " };
+                let (x, y) = svg_coords(v);
+                write!(out, "{}{},{}", sep, x, y)?;
+            }
+            writeln!(out, r#""/>"#)
+        };
+        let waypoint_style = format!(
+            r#"stroke="orange" stroke-width="{}" stroke-dasharray="{} {}""#,
+            stroke,
+            stroke * 4.0,
+            stroke * 4.0
+        );
+        polyline(&mut out, &mut waypoints.iter().copied(), &waypoint_style)?;
+        polyline(
+            &mut out,
+            &mut positions.iter().copied(),
+            &format!(r#"stroke="steelblue" stroke-width="{}""#, stroke * 2.0),
+        )?;
+        let (start, end) = (positions[0], positions[positions.len() - 1]);
+        if let Some(waypoint) = waypoints.last() {
+            let (x1, y1) = svg_coords(end);
+            let (x2, y2) = svg_coords(*waypoint);
+            writeln!(
+                out,
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="orange" stroke-width="{}" marker-end="url(#arrow)"/>"#,
+                x1,
+                y1,
+                x2,
+                y2,
+                stroke * 2.0
+            )?;
+        }
+        for (v, color) in &[(start, "green"), (end, "red")] {
+            let (x, y) = svg_coords(*v);
+            writeln!(
+                out,
+                r#"<circle cx="{}" cy="{}" r="{}" fill="{}"/>"#,
+                x,
+                y,
+                stroke * 5.0,
+                color
+            )?;
+        }
+        writeln!(out, "</svg>")
+    }
+}
+
+// SVG's y axis points down, so y is negated. Zeros are written without a
+// sign.
+fn svg_coords(v: Vector<f64>) -> (f64, f64) {
+    let unsigned = |c: f64| if c == 0.0 { 0.0 } else { c };
+    (unsigned(v.x), unsigned(-v.y))
+}
+
+#[derive(Debug, Clone)]
+pub struct Ship<N: Navigation> {
+    position: Vector<N::Coord>,
+    navigation: N,
+    track: Option<Track<N::Coord>>,
+}
+
+impl<N: Navigation> Ship<N> {
+    /// A ship at the origin.
+    pub fn new(navigation: N) -> Self {
+        let zero = N::Coord::from_i32(0);
+        Ship {
+            position: Vector::new(zero, zero),
+            navigation,
+            track: None,
+        }
+    }
+
+    /// A ship that records its route as it moves.
+    pub fn with_track(navigation: N) -> Self {
+        let mut ship = Ship::new(navigation);
+        ship.track = Some(Track {
+            points: vec![track_point(&ship.navigation, ship.position, None)],
+        });
+        ship
+    }
+
+    pub fn position(&self) -> Vector<N::Coord> {
+        self.position
+    }
+
+    pub fn navigation(&self) -> &N {
+        &self.navigation
+    }
+
+    /// The route taken so far, if the ship was created with `with_track`.
+    pub fn track(&self) -> Option<&Track<N::Coord>> {
+        self.track.as_ref()
+    }
+
+    /// Apply an action and record the resulting position on the track, if
+    /// there is one. A rejected action leaves the ship and its track
+    /// unchanged.
+    pub fn apply(&mut self, action: Action) -> Result<(), NavigationError> {
+        self.navigation.apply(&mut self.position, action)?;
+        if let Some(track) = &mut self.track {
+            track
+                .points
+                .push(track_point(&self.navigation, self.position, Some(action)));
         }
+        Ok(())
+    }
+
+    pub fn navigate<I>(&mut self, actions: I) -> Result<(), NavigationError>
+    where
+        I: IntoIterator<Item = Action>,
+    {
+        actions
+            .into_iter()
+            .try_for_each(|action| self.apply(action))
+    }
+
+    /// The manhattan distance from the origin.
+    pub fn distance(&self) -> <N::Coord as Coord>::Distance {
+        self.position.manhattan_distance()
+    }
+}
+
+fn track_point<N: Navigation>(
+    navigation: &N,
+    position: Vector<N::Coord>,
+    action: Option<Action>,
+) -> TrackPoint<N::Coord> {
+    TrackPoint {
+        action,
+        position,
+        waypoint: navigation.waypoint(),
+    }
+}
+
+fn parse_actions(input: &str) -> impl Iterator<Item = Action> + '_ {
+    input.split('\n').map(|x| Action::parse(x).unwrap())
+}
+
+#[aoc(day12, part1)]
+fn solve_d12_p1(input: &str) -> u128 {
+    let mut ship = Ship::new(Direct::<i64>::default());
+    ship.navigate(parse_actions(input)).unwrap();
+    ship.distance()
+}
+
+#[aoc(day12, part2)]
+fn solve_d12_p2(input: &str) -> u128 {
+    let mut ship = Ship::new(Waypoint::<i64>::default());
+    ship.navigate(parse_actions(input)).unwrap();
+    ship.distance()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "F10\nN3\nF7\nR90\nF11";
+
+    #[test]
+    fn test_example() {
+        assert_eq!(solve_d12_p1(EXAMPLE), 25);
+        assert_eq!(solve_d12_p2(EXAMPLE), 286);
+    }
+
+    #[test]
+    fn test_track() {
+        let mut untracked = Ship::new(Waypoint::<i64>::default());
+        untracked.navigate(parse_actions(EXAMPLE)).unwrap();
+        assert!(untracked.track().is_none());
+
+        let mut ship = Ship::with_track(Waypoint::<i64>::default());
+        ship.navigate(parse_actions(EXAMPLE)).unwrap();
+        let track = ship.track().unwrap();
+        assert_eq!(track.points().len(), 6);
+        assert_eq!(track.points()[0].waypoint, Some(Vector::new(10, 1)));
+        let end = track.points()[5];
+        assert_eq!(end.action, Some(Action::Forward(11)));
+        assert_eq!(end.position, Vector::new(214, -72));
+        assert_eq!(end.waypoint, Some(Vector::new(4, -10)));
+
+        let stats = track.stats();
+        assert_eq!(stats.min, Vector::new(0, -72));
+        assert_eq!(stats.max, Vector::new(214, 38));
+        assert_eq!(stats.farthest, Vector::new(214, -72));
+        let expected = 10.0 * 101f64.sqrt() + 18.0 * 116f64.sqrt();
+        assert!((stats.distance_traveled - expected).abs() < 1e-9);
+
+        let mut svg = Vec::new();
+        track.write_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("points=\"0,0 100,-10 100,-10 170,-38 170,-38 214,72\""));
+        assert!(svg.contains("x1=\"214\" y1=\"72\" x2=\"218\" y2=\"82\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+
+        let mut free = Ship::with_track(Direct::<f64>::default());
+        free.navigate(vec![Action::Left(45), Action::Forward(2)])
+            .unwrap();
+        let stats = free.track().unwrap().stats();
+        assert!((stats.distance_traveled - 2.0).abs() < 1e-9);
+        assert!((stats.max.x - 2f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_turns() {
+        let v = Vector::new(10i64, 4);
+        assert_eq!(v.rotate(0, LeftRight::Left), Ok(v));
+        assert_eq!(v.rotate(360, LeftRight::Right), Ok(v));
+        assert_eq!(v.rotate(90, LeftRight::Right), Ok(Vector::new(4, -10)));
+        assert_eq!(v.rotate(450, LeftRight::Left), Ok(Vector::new(-4, 10)));
+        assert_eq!(
+            v.rotate(45, LeftRight::Left),
+            Err(NavigationError::UnsupportedTurn { degrees: 45 })
+        );
+        assert_eq!(
+            Vector::new(i64::MIN, 0).rotate(180, LeftRight::Left),
+            Err(NavigationError::Overflow)
+        );
+
+        let mut ship = Ship::new(Direct::<f64>::default());
+        ship.navigate(vec![
+            Action::Left(45),
+            Action::Forward(2),
+            Action::Right(405),
+        ])
+        .unwrap();
+        let position = ship.position();
+        assert!((position.x - 2f64.sqrt()).abs() < 1e-9);
+        assert!((position.y - 2f64.sqrt()).abs() < 1e-9);
+        let heading = ship.navigation().heading;
+        assert!((heading.x - 1.0).abs() < 1e-9 && heading.y.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_large_distances() {
+        // Beyond 2^53 a float would lose the final step.
+        let mut ship = Ship::new(Direct::<i64>::default());
+        ship.navigate(vec![
+            Action::North(1 << 53),
+            Action::North(1),
+            Action::South(1 << 53),
+        ])
+        .unwrap();
+        assert_eq!(ship.distance(), 1);
+
+        // The waypoint starts 1 unit north, so this overflows and is rejected.
+        let mut far = Ship::new(Waypoint::<i64>::default());
+        let max = i64::MAX as usize;
+        assert_eq!(
+            far.apply(Action::North(max)),
+            Err(NavigationError::Overflow)
+        );
+        assert_eq!(far.navigation().offset, Vector::new(10, 1));
+        far.navigate(vec![Action::West(10), Action::North(max - 1)])
+            .unwrap();
+        far.apply(Action::Forward(1)).unwrap();
+        assert_eq!(far.position(), Vector::new(0, i64::MAX));
+        assert_eq!(
+            far.apply(Action::Forward(1)),
+            Err(NavigationError::Overflow)
+        );
+        assert_eq!(far.position(), Vector::new(0, i64::MAX));
+        far.navigate(vec![Action::Right(180), Action::Forward(1)])
+            .unwrap();
+        assert_eq!(far.distance(), 0);
     }
-    x.abs() as usize + y.abs() as usize
 }
@@ -1,95 +1,225 @@
 use aoc_runner_derive::aoc;
 
-#[aoc(day13, part1)]
-fn solve_d13_p1(input: &str) -> usize {
-    let first_newline = input.find('\n').unwrap();
-    let (t, bus_ids) = input.split_at(first_newline);
-    let bus_ids = bus_ids.trim();
+use crate::number_theory::{crt, Congruence, CrtError};
+use std::ops::Range;
+
+/// A bus and its position in the schedule list.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bus {
+    id: u64,
+    offset: u64,
+}
 
-    let t: usize = t.parse().unwrap();
+impl Bus {
+    /// Returns `None` if `id` is 0.
+    pub fn new(id: u64, offset: u64) -> Option<Self> {
+        if id == 0 {
+            None
+        } else {
+            Some(Bus { id, offset })
+        }
+    }
 
-    struct WaitTime {
-        bus_id: usize,
-        wait_time: usize,
+    /// The bus departs at every multiple of its id.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The index of the bus in the schedule.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The first departure at or after `t`.
+    pub fn next_departure(&self, t: u64) -> u64 {
+        match t % self.id {
+            0 => t,
+            rem => t + (self.id - rem),
+        }
     }
-    let min_wait_time = bus_ids
-        .split(',')
-        .filter(|&x| x != "x")
-        .map(|x| x.parse::<usize>().unwrap())
-        .fold(
-            WaitTime {
-                bus_id: usize::MAX,
-                wait_time: usize::MAX,
-            },
-            |min, bus_id| {
-                let offset = t % bus_id;
-                let wait_time = if offset == 0 { 0 } else { bus_id - offset };
-                if min.wait_time < wait_time {
-                    min
-                } else {
-                    WaitTime { bus_id, wait_time }
-                }
-            },
-        );
-    min_wait_time.bus_id * min_wait_time.wait_time
 }
 
-// Compute the modular inverse t such that a*t ≡ 1 mod n
-// https://en.wikipedia.org/wiki/Extended_Euclidean_algorithm#Modular_integers
-fn inverse(a: isize, n: isize) -> isize {
-    let mut t = 0;
-    let mut r = n;
-    let mut newt = 1;
-    let mut newr = a;
-
-    while newr != 0 {
-        let q = r / newr;
-        let prev_t = t;
-        t = newt;
-        newt = prev_t - q * newt;
-        let prev_r = r;
-        r = newr;
-        newr = prev_r - q * newr;
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Departure {
+    pub bus_id: u64,
+    pub time: u64,
+}
+
+/// When a set of buses depart at their offsets from a common timestamp.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    /// The earliest non-negative timestamp the pattern occurs at.
+    pub earliest: u128,
+    /// How often the pattern repeats.
+    pub period: u128,
+}
+
+impl Pattern {
+    /// The earliest timestamp at which every bus departs `offset` minutes
+    /// after it. Bus ids don't need to be coprime.
+    pub fn find<I>(buses: I) -> Result<Self, CrtError>
+    where
+        I: IntoIterator<Item = Bus>,
+    {
+        let solution = crt(buses.into_iter().map(|bus| {
+            let (id, offset) = (bus.id as u128, bus.offset as u128);
+            Congruence {
+                residue: (id - offset % id) % id,
+                modulus: id,
+            }
+        }))?;
+        Ok(Pattern {
+            earliest: solution.residue,
+            period: solution.modulus,
+        })
+    }
+}
+
+/// The buses in service, ignoring the `x` entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timetable {
+    buses: Vec<Bus>,
+}
+
+impl Timetable {
+    /// Parse a comma separated list of bus ids. Returns `None` if an id is
+    /// invalid or zero.
+    pub fn parse(bus_ids: &str) -> Option<Self> {
+        let buses = bus_ids
+            .trim()
+            .split(',')
+            .enumerate()
+            .filter(|&(_, id)| id != "x")
+            .map(|(offset, id)| Bus::new(id.parse().ok()?, offset as u64))
+            .collect::<Option<_>>()?;
+        Some(Timetable { buses })
+    }
+
+    pub fn buses(&self) -> &[Bus] {
+        &self.buses
+    }
+
+    pub fn bus(&self, id: u64) -> Option<Bus> {
+        self.buses.iter().copied().find(|bus| bus.id == id)
+    }
+
+    /// The first departure of each bus at or after `t`, in schedule order.
+    pub fn next_departures(&self, t: u64) -> Vec<Departure> {
+        self.buses
+            .iter()
+            .map(|bus| Departure {
+                bus_id: bus.id,
+                time: bus.next_departure(t),
+            })
+            .collect()
     }
 
-    if r > 1 {
-        panic!("{a} is not invertible");
+    /// The earliest departure at or after `t`. Ties go to the bus listed
+    /// first.
+    pub fn earliest_departure(&self, t: u64) -> Option<Departure> {
+        self.next_departures(t).into_iter().min_by_key(|d| d.time)
     }
-    if t < 0 {
-        t += n;
+
+    /// Every departure within `window`, ordered by time and then by
+    /// schedule order.
+    pub fn departures_between(&self, window: Range<u64>) -> Vec<Departure> {
+        let mut departures: Vec<Departure> = self
+            .buses
+            .iter()
+            .flat_map(|bus| {
+                (bus.next_departure(window.start)..window.end)
+                    .step_by(bus.id as usize)
+                    .map(move |time| Departure {
+                        bus_id: bus.id,
+                        time,
+                    })
+            })
+            .collect();
+        departures.sort_by_key(|d| d.time);
+        departures
+    }
+
+    /// The pattern formed by every bus departing at its offset in the
+    /// schedule.
+    pub fn pattern(&self) -> Result<Pattern, CrtError> {
+        Pattern::find(self.buses.iter().copied())
     }
-    t
 }
 
-// All the bus numbers are prime numbers. Use the chinese remainder theorem:
-// https://www.youtube.com/watch?v=MdePzlQtnCc
+fn parse_notes(input: &str) -> (u64, Timetable) {
+    let (t, bus_ids) = input.split_once('\n').unwrap();
+    (t.parse().unwrap(), Timetable::parse(bus_ids).unwrap())
+}
+
+#[aoc(day13, part1)]
+fn solve_d13_p1(input: &str) -> u64 {
+    let (t, timetable) = parse_notes(input);
+    let departure = timetable.earliest_departure(t).unwrap();
+    departure.bus_id * (departure.time - t)
+}
+
 #[aoc(day13, part2)]
-fn solve_d13_p2(input: &str) -> usize {
-    let (_, bus_ids) = input.split_once('\n').unwrap();
-    let mut ri = Vec::new();
-    let mut mods: Vec<usize> = Vec::new();
-    let mut unique_mod = 0;
-    for (time_after, bus_id) in bus_ids.split(',').enumerate() {
-        if bus_id == "x" {
-            continue;
-        }
-        let bus_id: usize = bus_id.parse().unwrap();
-        mods.push(bus_id);
-        ri.push((bus_id - time_after % bus_id) % bus_id);
+fn solve_d13_p2(input: &str) -> u128 {
+    let (_, timetable) = parse_notes(input);
+    timetable.pattern().unwrap().earliest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "939\n7,13,x,x,59,x,31,19";
+
+    #[test]
+    fn test_example() {
+        assert_eq!(solve_d13_p1(EXAMPLE), 295);
+        assert_eq!(solve_d13_p2(EXAMPLE), 1068781);
     }
 
-    let unique_mod: usize = mods.iter().product();
-    let mi: Vec<_> = mods.iter().map(|m| unique_mod / m).collect();
-    let xi: Vec<_> = mods
-        .iter()
-        .zip(mi.iter())
-        .map(|(&m, &mi): (&usize, &usize)| inverse(mi as isize, m as isize) as usize)
-        .collect();
-
-    ri.iter()
-        .copied()
-        .zip(mi.iter().copied().zip(xi.iter().copied()))
-        .map(|(ri, (mi, xi))| ri * mi * xi)
-        .sum::<usize>()
-        % unique_mod
+    #[test]
+    fn test_timetable() {
+        let timetable = Timetable::parse("7,13,x,x,59,x,31,19").unwrap();
+        assert_eq!(timetable.next_departures(939)[2].time, 944);
+        assert_eq!(
+            timetable.departures_between(940..946),
+            vec![
+                Departure {
+                    bus_id: 59,
+                    time: 944
+                },
+                Departure {
+                    bus_id: 7,
+                    time: 945
+                },
+            ]
+        );
+
+        let subset = [13, 59].iter().map(|&id| timetable.bus(id).unwrap());
+        let pattern = Pattern::find(subset).unwrap();
+        assert_eq!(pattern.period, 13 * 59);
+        assert_eq!(pattern.earliest % 13, 12);
+        assert_eq!(pattern.earliest % 59, 55);
+
+        assert_eq!(
+            Timetable::parse("17,x,13,19").unwrap().pattern(),
+            Ok(Pattern {
+                earliest: 3417,
+                period: 17 * 13 * 19
+            })
+        );
+        // Not coprime: 4 and 6 both depart at 4 + 12n when offset by 2.
+        assert_eq!(
+            Timetable::parse("4,x,6").unwrap().pattern(),
+            Ok(Pattern {
+                earliest: 4,
+                period: 12
+            })
+        );
+        assert_eq!(
+            Timetable::parse("4,6").unwrap().pattern(),
+            Err(CrtError::Inconsistent { index: 1 })
+        );
+        assert_eq!(Bus::new(0, 1), None);
+        assert_eq!(Timetable::parse("0,5"), None);
+    }
 }
@@ -2,18 +2,18 @@ use aoc_runner_derive::aoc;
 use std::collections::HashMap;
 
 #[derive(Debug)]
-enum Instr<'a> {
-    Mask(&'a str),
+enum Instr {
+    Mask(Masks),
     UpdateMem { offset: u64, value: u64 },
 }
 
-impl<'a> Instr<'a> {
-    fn parse(instr: &'a str) -> Option<Self> {
+impl Instr {
+    fn parse(instr: &str) -> Option<Self> {
         let eq_idx = instr.find('=')?;
         let lhs = &instr[..eq_idx - 1];
         let rhs = &instr[eq_idx + 2..];
         if lhs == "mask" {
-            Some(Instr::Mask(rhs))
+            Some(Instr::Mask(Masks::parse(rhs)?))
         } else {
             Self::parse_mem(lhs, rhs)
         }
@@ -26,7 +26,23 @@ impl<'a> Instr<'a> {
     }
 }
 
-#[derive(Debug)]
+// A mask line decoded for both versions of the decoder chip.
+#[derive(Debug, Copy, Clone)]
+struct Masks {
+    v1: Mask,
+    v2: Mask2,
+}
+
+impl Masks {
+    fn parse(mask: &str) -> Option<Self> {
+        Some(Masks {
+            v1: Mask::parse(mask.as_bytes())?,
+            v2: Mask2::parse(mask.as_bytes())?,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
 struct Mask {
     or_mask: u64,
     and_mask: u64,
@@ -60,24 +76,11 @@ impl Mask {
 
 #[aoc(day14, part1)]
 fn solve_d14_p1(input: &str) -> u64 {
-    // Assume initialization doesn't write to any memory offset multiple times.
-    let mut iter = input.split('\n').map(|x| Instr::parse(x).unwrap());
-    let mut mask = match iter.next() {
-        Some(Instr::Mask(mask)) => Mask::parse(mask.as_bytes()).unwrap(),
-        _ => panic!("first line is not valid bitmask"),
-    };
-    let mut memory = std::collections::HashMap::new();
-    for instr in iter {
-        match instr {
-            Instr::Mask(new_mask) => mask = Mask::parse(new_mask.as_bytes()).unwrap(),
-            Instr::UpdateMem { offset, value } => {
-                memory.insert(offset, mask.mask(value));
-            }
-        }
-    }
-    memory.values().sum()
+    let program = Program::parse(input).unwrap();
+    program.run_v1().values().sum()
 }
 
+#[derive(Debug, Copy, Clone)]
 struct Mask2 {
     floating: u64,
     or_mask: u64,
@@ -105,24 +108,143 @@ impl Mask2 {
         Some(Mask2 { floating, or_mask })
     }
 
-    fn set_memory(&self, mem: &mut HashMap<u64, u64>, address: u64, value: u64) {
-        let offset = address | self.or_mask;
-        for mut floating_value in 0..2 << self.floating.count_ones() {
-            let mut addr = offset;
-            for bit in BitIndexes::new(self.floating) {
-                addr = set_bit(addr, bit, (floating_value & 1) == 1);
-                floating_value >>= 1;
-            }
-            mem.insert(addr, value);
+    fn addresses(&self, address: u64) -> AddressPattern {
+        AddressPattern::new(address | self.or_mask, self.floating)
+    }
+}
+
+/// A set of addresses where the floating bits can take either value and the
+/// remaining bits are fixed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AddressPattern {
+    fixed: u64,
+    floating: u64,
+}
+
+impl AddressPattern {
+    pub fn new(address: u64, floating: u64) -> Self {
+        AddressPattern {
+            fixed: address & !floating,
+            floating,
+        }
+    }
+
+    /// The number of addresses matched.
+    pub fn address_count(&self) -> u128 {
+        1 << self.floating.count_ones()
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address & !self.floating == self.fixed
+    }
+
+    /// The addresses matched by both patterns, if any.
+    pub fn intersection(&self, other: &AddressPattern) -> Option<AddressPattern> {
+        let both_fixed = !self.floating & !other.floating;
+        if (self.fixed ^ other.fixed) & both_fixed != 0 {
+            return None;
+        }
+        Some(AddressPattern {
+            fixed: self.fixed | other.fixed,
+            floating: self.floating & other.floating,
+        })
+    }
+
+    /// The addresses matched by self but not other, as disjoint patterns.
+    pub fn subtract(&self, other: &AddressPattern) -> Vec<AddressPattern> {
+        let overlap = match self.intersection(other) {
+            Some(overlap) => overlap,
+            None => return vec![*self],
+        };
+        // Peel off one half of self for each bit that floats in self but is
+        // fixed in other. The half that disagrees with other on that bit is
+        // disjoint from it; the other half is narrowed further.
+        let mut pieces = Vec::new();
+        let mut remaining = *self;
+        for bit in BitIndexes::new(self.floating & !other.floating) {
+            let bit = 1 << bit;
+            remaining.floating &= !bit;
+            pieces.push(AddressPattern {
+                fixed: remaining.fixed | (!overlap.fixed & bit),
+                floating: remaining.floating,
+            });
+            remaining.fixed |= overlap.fixed & bit;
         }
+        pieces
     }
+
+    /// Every matched address in ascending order.
+    pub fn addresses(&self) -> impl Iterator<Item = u64> {
+        let AddressPattern { fixed, floating } = *self;
+        // Step through the subsets of floating in increasing order.
+        let mut next = Some(0u64);
+        std::iter::from_fn(move || {
+            let subset = next?;
+            next = if subset == floating {
+                None
+            } else {
+                Some((subset | !floating).wrapping_add(1) & floating)
+            };
+            Some(fixed | subset)
+        })
+    }
+}
+
+/// Memory written through floating address patterns. Writes are kept as
+/// disjoint patterns so sums can be computed without visiting every
+/// address.
+#[derive(Debug, Clone, Default)]
+pub struct FloatingMemory {
+    writes: Vec<(AddressPattern, u64)>,
 }
 
-fn set_bit(value: u64, bit_idx: u64, enabled: bool) -> u64 {
-    if enabled {
-        value | (1 << bit_idx)
-    } else {
-        value & !(1 << bit_idx)
+impl FloatingMemory {
+    pub fn new() -> Self {
+        FloatingMemory::default()
+    }
+
+    /// Write value to every address in the pattern. Earlier writes are
+    /// trimmed so they no longer overlap it.
+    pub fn write(&mut self, addresses: AddressPattern, value: u64) {
+        let writes = std::mem::take(&mut self.writes);
+        for (pattern, old_value) in writes {
+            for piece in pattern.subtract(&addresses) {
+                self.writes.push((piece, old_value));
+            }
+        }
+        self.writes.push((addresses, value));
+    }
+
+    pub fn get(&self, address: u64) -> Option<u64> {
+        self.writes
+            .iter()
+            .find(|(pattern, _)| pattern.contains(address))
+            .map(|&(_, value)| value)
+    }
+
+    /// The number of addresses that have been written.
+    pub fn address_count(&self) -> u128 {
+        self.writes.iter().map(|(p, _)| p.address_count()).sum()
+    }
+
+    /// Every written address and its value, sorted by address. This visits
+    /// every address so it's only practical when few bits float.
+    pub fn dump(&self) -> Vec<(u64, u64)> {
+        let mut dump: Vec<_> = self
+            .writes
+            .iter()
+            .flat_map(|&(pattern, value)| pattern.addresses().map(move |addr| (addr, value)))
+            .collect();
+        dump.sort_unstable();
+        dump
+    }
+
+    /// The sum of every written value.
+    pub fn sum(&self) -> u128 {
+        self.writes
+            .iter()
+            .map(|&(pattern, value)| pattern.address_count() * value as u128)
+            .sum()
     }
 }
 
@@ -154,21 +276,255 @@ impl Iterator for BitIndexes {
     }
 }
 
+/// A mask that leaves values unchanged in version 1 and floats every address
+/// bit in version 2.
+pub const ALL_FLOATING: &str = "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX";
+
+/// A parsed initialization program.
+#[derive(Debug)]
+pub struct Program {
+    instrs: Vec<Instr>,
+    initial_mask: Masks,
+}
+
+impl Program {
+    /// Parse a program, returning `None` if an instruction or mask is
+    /// invalid. Writes before the first mask instruction use `ALL_FLOATING`.
+    pub fn parse(input: &str) -> Option<Self> {
+        let instrs = input.split('\n').map(Instr::parse).collect::<Option<_>>()?;
+        Some(Program {
+            instrs,
+            initial_mask: Masks::parse(ALL_FLOATING)?,
+        })
+    }
+
+    /// Use a different mask for writes before the first mask instruction.
+    /// Returns `None` if the mask is invalid.
+    pub fn with_initial_mask(self, initial_mask: &str) -> Option<Self> {
+        Some(Program {
+            initial_mask: Masks::parse(initial_mask)?,
+            ..self
+        })
+    }
+
+    // The instructions with each mask resolved to the one in effect.
+    fn updates(&self) -> impl Iterator<Item = (Masks, u64, u64)> + '_ {
+        let mut mask = self.initial_mask;
+        self.instrs.iter().filter_map(move |instr| match *instr {
+            Instr::Mask(new_mask) => {
+                mask = new_mask;
+                None
+            }
+            Instr::UpdateMem { offset, value } => Some((mask, offset, value)),
+        })
+    }
+
+    /// Run with the version 1 decoder, which masks values.
+    pub fn run_v1(&self) -> HashMap<u64, u64> {
+        let mut memory = HashMap::new();
+        for (mask, offset, value) in self.updates() {
+            memory.insert(offset, mask.v1.mask(value));
+        }
+        memory
+    }
+
+    /// Run with the version 2 decoder, which masks addresses.
+    pub fn run_v2(&self) -> FloatingMemory {
+        let mut memory = FloatingMemory::new();
+        for (mask, offset, value) in self.updates() {
+            memory.write(mask.v2.addresses(offset), value);
+        }
+        memory
+    }
+
+    /// Compare the memory produced by the two decoders without visiting
+    /// every address written by version 2.
+    pub fn diff_decoders(&self) -> DecoderDiff {
+        let v1 = sorted_dump(&self.run_v1());
+        let v2 = self.run_v2();
+        let changed = v1
+            .iter()
+            .filter_map(|&(address, value)| {
+                let right = v2.get(address);
+                if right == Some(value) {
+                    None
+                } else {
+                    Some(MemoryDiff {
+                        address,
+                        left: Some(value),
+                        right,
+                    })
+                }
+            })
+            .collect();
+        let mut v2_only = Vec::new();
+        for &(pattern, value) in &v2.writes {
+            let mut pieces = vec![pattern];
+            for &(address, _) in v1.iter().filter(|&&(a, _)| pattern.contains(a)) {
+                let written = AddressPattern::new(address, 0);
+                pieces = pieces
+                    .iter()
+                    .flat_map(|piece| piece.subtract(&written))
+                    .collect();
+            }
+            v2_only.extend(pieces.into_iter().map(|piece| (piece, value)));
+        }
+        v2_only.sort_unstable_by_key(|&(p, _)| (p.fixed, p.floating));
+        DecoderDiff { changed, v2_only }
+    }
+}
+
+/// How the memory written by the two decoders differs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecoderDiff {
+    /// Addresses written by version 1 that hold something else after
+    /// version 2, in address order.
+    pub changed: Vec<MemoryDiff>,
+    /// The addresses only version 2 wrote, and their values.
+    pub v2_only: Vec<(AddressPattern, u64)>,
+}
+
+/// Memory contents as (address, value) pairs sorted by address.
+pub fn sorted_dump(memory: &HashMap<u64, u64>) -> Vec<(u64, u64)> {
+    let mut dump: Vec<_> = memory.iter().map(|(&addr, &value)| (addr, value)).collect();
+    dump.sort_unstable();
+    dump
+}
+
+/// An address whose value differs between two memory dumps. `None` means the
+/// address was never written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryDiff {
+    pub address: u64,
+    pub left: Option<u64>,
+    pub right: Option<u64>,
+}
+
+/// Every address that differs between two sorted dumps, in address order.
+pub fn diff_dumps(left: &[(u64, u64)], right: &[(u64, u64)]) -> Vec<MemoryDiff> {
+    let mut diffs = Vec::new();
+    let (mut left, mut right) = (left.iter().peekable(), right.iter().peekable());
+    loop {
+        let address = match (left.peek(), right.peek()) {
+            (None, None) => return diffs,
+            (Some(&&(address, _)), None) | (None, Some(&&(address, _))) => address,
+            (Some(&&(l, _)), Some(&&(r, _))) => l.min(r),
+        };
+        let take = |dump: &mut std::iter::Peekable<std::slice::Iter<(u64, u64)>>| {
+            dump.next_if(|&&(a, _)| a == address)
+                .map(|&(_, value)| value)
+        };
+        let (l, r) = (take(&mut left), take(&mut right));
+        if l != r {
+            diffs.push(MemoryDiff {
+                address,
+                left: l,
+                right: r,
+            });
+        }
+    }
+}
+
 #[aoc(day14, part2)]
-fn solve_d14_p2(input: &str) -> u64 {
-    let mut iter = input.split('\n').map(|x| Instr::parse(x).unwrap());
-    let mut mask = match iter.next() {
-        Some(Instr::Mask(mask)) => Mask2::parse(mask.as_bytes()).unwrap(),
-        _ => panic!("first line is not valid bitmask"),
-    };
-    let mut memory = std::collections::HashMap::new();
-    for instr in iter {
-        match instr {
-            Instr::Mask(new_mask) => mask = Mask2::parse(new_mask.as_bytes()).unwrap(),
-            Instr::UpdateMem { offset, value } => {
-                mask.set_memory(&mut memory, offset, value);
+fn solve_d14_p2(input: &str) -> u128 {
+    let program = Program::parse(input).unwrap();
+    program.run_v2().sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xorshift;
+
+    #[test]
+    fn test_example() {
+        let input = "mask = 000000000000000000000000000000X1001X\n\
+                     mem[42] = 100\n\
+                     mask = 00000000000000000000000000000000X0XX\n\
+                     mem[26] = 1";
+        assert_eq!(solve_d14_p2(input), 208);
+    }
+
+    #[test]
+    fn test_program() {
+        let input = "mem[8] = 11\n\
+                     mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X\n\
+                     mem[7] = 101\n\
+                     mem[8] = 0";
+        let program = Program::parse(input).unwrap();
+        assert_eq!(sorted_dump(&program.run_v1()), vec![(7, 101), (8, 64)]);
+
+        let input = "mem[8] = 11\n\
+                     mask = 0000000000000000000000000000000000X1\n\
+                     mem[4] = 3";
+        let mask = "000000000000000000000000000000000000";
+        let program = Program::parse(input)
+            .unwrap()
+            .with_initial_mask(mask)
+            .unwrap();
+        assert_eq!(program.run_v2().dump(), vec![(5, 3), (7, 3), (8, 11)]);
+        let diff = |address, left, right| MemoryDiff {
+            address,
+            left,
+            right,
+        };
+        assert_eq!(
+            program.diff_decoders(),
+            DecoderDiff {
+                changed: vec![diff(4, Some(3), None), diff(8, Some(0), Some(11))],
+                v2_only: vec![(AddressPattern::new(5, 2), 3)],
             }
+        );
+        assert_eq!(
+            diff_dumps(&sorted_dump(&program.run_v1()), &program.run_v2().dump()),
+            vec![
+                diff(4, Some(3), None),
+                diff(5, None, Some(3)),
+                diff(7, None, Some(3)),
+                diff(8, Some(0), Some(11)),
+            ]
+        );
+        assert!(Program::parse(input)
+            .unwrap()
+            .with_initial_mask("bogus")
+            .is_none());
+        assert!(Program::parse("mask = 01X").is_none());
+
+        // The default initial mask floats every bit, so the first write
+        // covers all 2^36 addresses.
+        let diff_decoders = Program::parse(input).unwrap().diff_decoders();
+        assert_eq!(diff_decoders.changed, vec![diff(4, Some(3), Some(11))]);
+        let only_count = |value| {
+            diff_decoders
+                .v2_only
+                .iter()
+                .filter(|&&(_, v)| v == value)
+                .map(|(p, _)| p.address_count())
+                .sum::<u128>()
+        };
+        assert_eq!(only_count(11), (1 << 36) - 4);
+        assert_eq!(only_count(3), 2);
+    }
+
+    #[test]
+    fn test_floating_memory() {
+        // Compare against writing every address into a HashMap.
+        let mut rng = xorshift(0x9e37_79b9_7f4a_7c15);
+        let mut memory = FloatingMemory::new();
+        let mut reference = HashMap::new();
+        for value in 0..200 {
+            let floating = rng() & rng() & 0xff;
+            let pattern = AddressPattern::new(rng() & 0xff, floating);
+            memory.write(pattern, value);
+            for address in pattern.addresses() {
+                reference.insert(address, value);
+            }
+            assert_eq!(pattern.addresses().count() as u128, pattern.address_count());
+        }
+        assert_eq!(memory.address_count(), reference.len() as u128);
+        assert_eq!(memory.sum(), reference.values().sum::<u64>() as u128);
+        for address in 0..0x100 {
+            assert_eq!(memory.get(address), reference.get(&address).copied());
         }
     }
-    memory.values().sum()
 }
@@ -1,53 +1,166 @@
 use aoc_runner_derive::aoc;
 
-use std::collections::{hash_map::Entry, HashMap};
-
-fn spoken_word_n(mut spoken: HashMap<usize, usize>, mut last_spoken: usize, n: usize) -> usize {
-    for turn in spoken.len() + 1..=n {
-        //dbg!(turn, last_spoken);
-        last_spoken = match spoken.entry(last_spoken) {
-            Entry::Occupied(mut occupied) => {
-                let difference = turn - 1 - *occupied.get();
-                occupied.insert(turn - 1);
-                difference
+use std::collections::HashMap;
+
+/// The Van Eck style memory game. Iterating yields the number spoken on each
+/// turn, starting with the starting numbers. Turns are counted from 1 and
+/// the game ends after `u32::MAX` turns.
+///
+/// The turn each number was last spoken is kept in a dense array for numbers
+/// below the dense limit and in a `HashMap` for the rest. Every number spoken
+/// after the starting numbers is smaller than the current turn, so a limit
+/// equal to the number of turns played keeps everything but large starting
+/// numbers in the array.
+#[derive(Debug, Clone)]
+pub struct MemoryGame {
+    starting: Vec<u32>,
+    dense_limit: usize,
+    // Indexed by number; 0 means never spoken. Grows on demand up to
+    // dense_limit.
+    dense: Vec<u32>,
+    sparse: HashMap<u32, u32>,
+    turn: u32,
+    next: u32,
+    last_was_new: bool,
+}
+
+impl MemoryGame {
+    pub const DEFAULT_DENSE_LIMIT: usize = 1 << 20;
+
+    pub fn new(starting: Vec<u32>) -> Self {
+        MemoryGame::with_dense_limit(starting, MemoryGame::DEFAULT_DENSE_LIMIT)
+    }
+
+    pub fn with_dense_limit(starting: Vec<u32>, dense_limit: usize) -> Self {
+        MemoryGame {
+            starting,
+            dense_limit,
+            dense: Vec::new(),
+            sparse: HashMap::new(),
+            turn: 0,
+            next: 0,
+            last_was_new: false,
+        }
+    }
+
+    pub fn parse(input: &str) -> Option<Self> {
+        let starting = input
+            .trim()
+            .split(',')
+            .map(|x| x.parse().ok())
+            .collect::<Option<_>>()?;
+        Some(MemoryGame::new(starting))
+    }
+
+    /// The number spoken on `turn`, or `None` if there are no starting
+    /// numbers or turn is 0. Only uses the dense array up to `turn`.
+    pub fn spoken_at(starting: &[u32], turn: u32) -> Option<u32> {
+        MemoryGame::with_dense_limit(starting.to_vec(), turn as usize)
+            .nth(turn.checked_sub(1)? as usize)
+    }
+
+    /// The number of turns played so far.
+    pub fn turn(&self) -> u32 {
+        self.turn
+    }
+
+    /// The most recent turn `number` was spoken on.
+    pub fn last_spoken_turn(&self, number: u32) -> Option<u32> {
+        match self.dense.get(number as usize) {
+            Some(0) => None,
+            Some(&turn) => Some(turn),
+            None => self.sparse.get(&number).copied(),
+        }
+    }
+
+    /// Whether the number spoken on the latest turn had never been spoken
+    /// before.
+    pub fn last_was_new(&self) -> bool {
+        self.last_was_new
+    }
+
+    /// Play `turns` turns and collect statistics.
+    pub fn stats(mut self, turns: u32) -> GameStats {
+        let mut first_spoken = HashMap::new();
+        let mut last = None;
+        for _ in 0..turns {
+            let spoken = match self.next() {
+                Some(spoken) => spoken,
+                None => break,
+            };
+            if self.last_was_new {
+                first_spoken.insert(spoken, self.turn);
+            }
+            last = Some(spoken);
+        }
+        GameStats {
+            turns: self.turn,
+            last,
+            first_spoken,
+        }
+    }
+
+    // Record that number was spoken this turn and return the turn it was
+    // previously spoken on.
+    fn record(&mut self, number: u32, turn: u32) -> Option<u32> {
+        let idx = number as usize;
+        if idx < self.dense_limit {
+            if idx >= self.dense.len() {
+                let len = (idx + 1).max(self.dense.len() * 2).min(self.dense_limit);
+                self.dense.resize(len, 0);
             }
-            Entry::Vacant(vacant) => {
-                vacant.insert(turn - 1);
-                0
+            match std::mem::replace(&mut self.dense[idx], turn) {
+                0 => None,
+                previous => Some(previous),
             }
+        } else {
+            self.sparse.insert(number, turn)
         }
     }
-    last_spoken
+}
+
+impl Iterator for MemoryGame {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.starting.is_empty() {
+            return None;
+        }
+        let turn = self.turn.checked_add(1)?;
+        let spoken = match self.starting.get(turn as usize - 1) {
+            Some(&starting) => starting,
+            None => self.next,
+        };
+        let previous = self.record(spoken, turn);
+        self.last_was_new = previous.is_none();
+        self.next = previous.map_or(0, |previous| turn - previous);
+        self.turn = turn;
+        Some(spoken)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameStats {
+    /// The number of turns actually played.
+    pub turns: u32,
+    /// The number spoken on the final turn.
+    pub last: Option<u32>,
+    /// The turn each distinct number was first spoken on.
+    pub first_spoken: HashMap<u32, u32>,
+}
+
+fn parse_starting(input: &str) -> Vec<u32> {
+    input.split(',').map(|x| x.parse().unwrap()).collect()
 }
 
 #[aoc(day15, part1)]
-fn solve_d15_p1(input: &str) -> usize {
-    let mut last_spoken = None;
-    let spoken: HashMap<usize, usize> = input
-        .split(',')
-        .enumerate()
-        .map(|(idx, spoken)| {
-            let spoken = spoken.parse().unwrap();
-            last_spoken = Some(spoken);
-            (spoken, idx + 1)
-        })
-        .collect();
-    spoken_word_n(spoken, last_spoken.unwrap(), 2020)
+fn solve_d15_p1(input: &str) -> u32 {
+    MemoryGame::spoken_at(&parse_starting(input), 2020).unwrap()
 }
 
 #[aoc(day15, part2)]
-fn solve_d15_p2(input: &str) -> usize {
-    let mut last_spoken = None;
-    let spoken: HashMap<usize, usize> = input
-        .split(',')
-        .enumerate()
-        .map(|(idx, spoken)| {
-            let spoken = spoken.parse().unwrap();
-            last_spoken = Some(spoken);
-            (spoken, idx + 1)
-        })
-        .collect();
-    spoken_word_n(spoken, last_spoken.unwrap(), 30_000_000)
+fn solve_d15_p2(input: &str) -> u32 {
+    MemoryGame::spoken_at(&parse_starting(input), 30_000_000).unwrap()
 }
 
 #[cfg(test)]
@@ -57,5 +170,36 @@ mod tests {
     #[test]
     fn test_part1() {
         assert_eq!(solve_d15_p1("1,3,2"), 1);
+        assert_eq!(solve_d15_p1("0,3,6"), 436);
+    }
+
+    #[test]
+    fn test_game() {
+        let game = MemoryGame::parse("0,3,6").unwrap();
+        assert_eq!(
+            game.take(10).collect::<Vec<_>>(),
+            vec![0, 3, 6, 0, 3, 3, 1, 0, 4, 0]
+        );
+        // Repeated and large starting numbers, with a tiny dense array.
+        let starting = vec![5, 5, 4_000_000_000];
+        let small = MemoryGame::with_dense_limit(starting.clone(), 2);
+        let large = MemoryGame::with_dense_limit(starting, 1 << 10);
+        assert!(small.take(500).eq(large.take(500)));
+        assert_eq!(
+            MemoryGame::parse("5,5,4000000000")
+                .unwrap()
+                .take(5)
+                .collect::<Vec<_>>(),
+            vec![5, 5, 4_000_000_000, 0, 0]
+        );
+        assert_eq!(MemoryGame::spoken_at(&[], 10), None);
+        assert_eq!(MemoryGame::spoken_at(&[1], 0), None);
+
+        let stats = MemoryGame::parse("0,3,6").unwrap().stats(10);
+        assert_eq!(stats.turns, 10);
+        assert_eq!(stats.last, Some(0));
+        let mut first_spoken: Vec<_> = stats.first_spoken.into_iter().collect();
+        first_spoken.sort_unstable();
+        assert_eq!(first_spoken, vec![(0, 1), (1, 7), (3, 2), (4, 9), (6, 3)]);
     }
 }
@@ -1,304 +1,810 @@
 use crate::split_once;
 use aoc_runner_derive::aoc;
+use std::fmt;
 use std::ops::RangeInclusive;
 
-struct Rule<'a> {
-    name: &'a str,
-    a: RangeInclusive<u16>,
-    b: RangeInclusive<u16>,
+/// A named field rule. A value is valid for the field if it falls within any
+/// of the ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule<'a> {
+    pub name: &'a str,
+    pub ranges: Vec<RangeInclusive<u16>>,
 }
 impl<'a> Rule<'a> {
-    fn parse(input: &'a str) -> Option<Self> {
+    pub fn parse(input: &'a str) -> Option<Self> {
         let (name, rem) = split_once(input, ": ")?;
-        let (a, b) = split_once(rem, " or ")?;
-        let a = {
-            let (start, end) = split_once(a, "-")?;
-            RangeInclusive::<u16>::new(start.parse().ok()?, end.parse().ok()?)
-        };
-        let b = {
-            let (start, end) = split_once(b, "-")?;
-            RangeInclusive::<u16>::new(start.parse().ok()?, end.parse().ok()?)
-        };
-        Some(Rule { name, a, b })
+        let ranges = rem
+            .split(" or ")
+            .map(|range| {
+                let (start, end) = split_once(range, "-")?;
+                Some(RangeInclusive::<u16>::new(
+                    start.parse().ok()?,
+                    end.parse().ok()?,
+                ))
+            })
+            .collect::<Option<_>>()?;
+        Some(Rule { name, ranges })
     }
 
-    fn matches(&self, value: u16) -> bool {
-        self.a.contains(&value) || self.b.contains(&value)
+    pub fn matches(&self, value: u16) -> bool {
+        self.ranges.iter().any(|range| range.contains(&value))
     }
 }
 
-#[aoc(day16, part1)]
-fn solve_d16_p1(input: &str) -> u16 {
-    let (rules, rem) = split_once(input, "\n\nyour ticket:\n").unwrap();
-    let (_your_ticket, nearby_tickets) = split_once(rem, "\n\nnearby tickets:\n").unwrap();
-
-    let rules: Vec<_> = rules.split('\n').map(|x| Rule::parse(x).unwrap()).collect();
-    nearby_tickets
-        .split('\n')
-        .flat_map(|line| line.split(','))
-        .filter_map(|x| {
-            let value: u16 = x.parse().unwrap();
-            if rules.iter().any(|rule| rule.matches(value)) {
-                None
-            } else {
-                Some(value)
-            }
-        })
-        .sum()
+/// How one value on a ticket fared against the rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueReport {
+    /// The position of the value on the ticket.
+    pub field: usize,
+    pub value: u16,
+    /// The indices of every rule the value satisfies.
+    pub satisfied: Vec<usize>,
 }
 
-#[aoc(day16, part2)]
-fn solve_d16_p2(input: &str) -> usize {
-    let (rules, rem) = split_once(input, "\n\nyour ticket:\n").unwrap();
-    let (my_ticket, nearby_tickets) = split_once(rem, "\n\nnearby tickets:\n").unwrap();
-
-    let rules: Vec<_> = rules.split('\n').map(|x| Rule::parse(x).unwrap()).collect();
-    assert!(rules.len() < 63);
-    let mut candidates = vec![(1u64 << rules.len()) - 1; rules.len()];
-    let mut scratch = Vec::with_capacity(rules.len());
-    for line in nearby_tickets.split('\n') {
-        scratch.clear();
-        scratch.extend(line.split(',').map(|field| {
-            let value = field.parse().unwrap();
-            // Initialize a bitmap of which rules the field is valid for. `1`
-            // indicates the value is valid for that field. `0` is invalid.
-            let mut bitmap = 0u64;
-            for (rule_idx, rule) in rules.iter().enumerate() {
-                if rule.matches(value) {
-                    bitmap |= 1 << rule_idx;
+impl ValueReport {
+    /// A value is invalid if it satisfies no rule at all.
+    pub fn is_valid(&self) -> bool {
+        !self.satisfied.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TicketReport {
+    pub values: Vec<ValueReport>,
+}
+
+impl TicketReport {
+    pub fn is_valid(&self) -> bool {
+        self.values.iter().all(ValueReport::is_valid)
+    }
+
+    pub fn invalid_values(&self) -> impl Iterator<Item = &ValueReport> {
+        self.values.iter().filter(|value| !value.is_valid())
+    }
+
+    /// The sum of the invalid values.
+    pub fn error_rate(&self) -> u64 {
+        self.invalid_values().map(|value| value.value as u64).sum()
+    }
+}
+
+/// The result of checking every nearby ticket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanReport {
+    pub tickets: Vec<TicketReport>,
+}
+
+impl ScanReport {
+    /// The ticket scanning error rate: the sum of every invalid value on
+    /// every nearby ticket.
+    pub fn error_rate(&self) -> u64 {
+        self.tickets.iter().map(TicketReport::error_rate).sum()
+    }
+
+    pub fn valid_tickets(&self) -> impl Iterator<Item = &TicketReport> {
+        self.tickets.iter().filter(|ticket| ticket.is_valid())
+    }
+
+    pub fn invalid_tickets(&self) -> impl Iterator<Item = &TicketReport> {
+        self.tickets.iter().filter(|ticket| !ticket.is_valid())
+    }
+}
+
+/// The puzzle input: the rules, your ticket and the nearby tickets.
+pub struct Notes<'a> {
+    rules: Vec<Rule<'a>>,
+    my_ticket: Vec<u16>,
+    nearby_tickets: Vec<Vec<u16>>,
+}
+
+impl<'a> Notes<'a> {
+    pub fn parse(input: &'a str) -> Option<Self> {
+        let (rules, rem) = split_once(input, "\n\nyour ticket:\n")?;
+        let (my_ticket, nearby_tickets) = split_once(rem, "\n\nnearby tickets:\n")?;
+        let parse_ticket =
+            |line: &str| -> Option<Vec<u16>> { line.split(',').map(|x| x.parse().ok()).collect() };
+        Some(Notes {
+            rules: rules.split('\n').map(Rule::parse).collect::<Option<_>>()?,
+            my_ticket: parse_ticket(my_ticket)?,
+            nearby_tickets: nearby_tickets
+                .split('\n')
+                .map(parse_ticket)
+                .collect::<Option<_>>()?,
+        })
+    }
+
+    pub fn rules(&self) -> &[Rule<'a>] {
+        &self.rules
+    }
+
+    pub fn my_ticket(&self) -> &[u16] {
+        &self.my_ticket
+    }
+
+    pub fn nearby_tickets(&self) -> &[Vec<u16>] {
+        &self.nearby_tickets
+    }
+
+    fn matches_any(&self, value: u16) -> bool {
+        self.rules.iter().any(|rule| rule.matches(value))
+    }
+
+    /// The ticket scanning error rate: the sum of every nearby ticket value
+    /// that matches no rule. The same as `scan().error_rate()` without
+    /// building the report.
+    pub fn error_rate(&self) -> u64 {
+        self.nearby_tickets
+            .iter()
+            .flatten()
+            .filter(|&&value| !self.matches_any(value))
+            .map(|&value| value as u64)
+            .sum()
+    }
+
+    /// Check every value on a ticket against every rule.
+    pub fn scan_ticket(&self, ticket: &[u16]) -> TicketReport {
+        let values = ticket
+            .iter()
+            .enumerate()
+            .map(|(field, &value)| ValueReport {
+                field,
+                value,
+                satisfied: (0..self.rules.len())
+                    .filter(|&rule_idx| self.rules[rule_idx].matches(value))
+                    .collect(),
+            })
+            .collect();
+        TicketReport { values }
+    }
+
+    pub fn scan(&self) -> ScanReport {
+        ScanReport {
+            tickets: self
+                .nearby_tickets
+                .iter()
+                .map(|ticket| self.scan_ticket(ticket))
+                .collect(),
+        }
+    }
+
+    /// For each field, the indices of the rules that every valid nearby
+    /// ticket satisfies. Tickets with a value that matches no rule are
+    /// ignored.
+    pub fn candidates(&self) -> Vec<Vec<usize>> {
+        let mut possible = vec![vec![true; self.rules.len()]; self.my_ticket.len()];
+        let valid_tickets = self
+            .nearby_tickets
+            .iter()
+            .filter(|ticket| ticket.iter().all(|&value| self.matches_any(value)));
+        for ticket in valid_tickets {
+            for (row, &value) in possible.iter_mut().zip(ticket) {
+                for (possible, rule) in row.iter_mut().zip(&self.rules) {
+                    *possible = *possible && rule.matches(value);
                 }
             }
-            bitmap
-        }));
-        if scratch.iter().copied().any(|x| x == 0) {
-            continue;
+        }
+        possible
+            .iter()
+            .map(|row| (0..row.len()).filter(|&idx| row[idx]).collect())
+            .collect()
+    }
+
+    /// The same as `candidates`, but evaluating the rules with `eval`.
+    pub fn candidates_with(&self, eval: &RuleEval) -> Vec<Vec<usize>> {
+        let words = eval.words();
+        let mut candidates = vec![!0u32; words * self.my_ticket.len()];
+        let mut scratch = vec![0u32; words * self.my_ticket.len()];
+        for ticket in &self.nearby_tickets {
+            for (&value, out) in ticket.iter().zip(scratch.chunks_mut(words)) {
+                eval.eval(value, out);
+            }
+            if scratch
+                .chunks(words)
+                .any(|bitmap| bitmap.iter().all(|&word| word == 0))
+            {
+                continue;
+            }
+            for (candidate, &valid) in candidates.iter_mut().zip(&scratch) {
+                *candidate &= valid;
+            }
         }
         candidates
-            .iter_mut()
-            .zip(scratch.iter())
-            .for_each(|(candidate, valid_bitmask)| {
-                *candidate &= valid_bitmask;
-            });
-    }
-    while candidates.iter().copied().any(|x| x.count_ones() > 1) {
-        for idx in 0..candidates.len() {
-            let candidate = candidates[idx];
-            if candidate.count_ones() == 1 {
-                let mask = !candidate;
-                for before in &mut candidates[..idx] {
-                    *before &= mask;
-                }
-                for after in &mut candidates[idx + 1..] {
-                    *after &= mask;
+            .chunks(words)
+            .map(|bitmap| {
+                (0..self.rules.len())
+                    .filter(|&idx| bitmap[idx / 32] & (1 << (idx % 32)) != 0)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The name of each field on your ticket paired with its value, in
+    /// ticket order.
+    pub fn my_ticket_fields(&self) -> Result<Vec<(&'a str, u16)>, AssignmentError> {
+        let assignment = unique_assignment(&self.candidates(), self.rules.len())?;
+        Ok(self.label_my_ticket(&assignment))
+    }
+
+    fn label_my_ticket(&self, assignment: &[usize]) -> Vec<(&'a str, u16)> {
+        assignment
+            .iter()
+            .zip(&self.my_ticket)
+            .map(|(&rule_idx, &value)| (self.rules[rule_idx].name, value))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssignmentError {
+    /// No assignment gives every field a different rule.
+    Unsatisfiable,
+    /// More than one assignment is consistent with the tickets. Two of them
+    /// are included, as rule indices for each field.
+    Ambiguous {
+        first: Vec<usize>,
+        second: Vec<usize>,
+    },
+}
+
+impl fmt::Display for AssignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssignmentError::Unsatisfiable => {
+                write!(f, "no assignment of rules to fields satisfies every ticket")
+            }
+            AssignmentError::Ambiguous { first, second } => {
+                write!(f, "more than one assignment of rules to fields is possible")?;
+                match first.iter().zip(second).position(|(a, b)| a != b) {
+                    Some(field) => write!(
+                        f,
+                        ": field {} could use rule {} or {}",
+                        field, first[field], second[field]
+                    ),
+                    None => Ok(()),
                 }
             }
         }
     }
-    my_ticket
-        .split(',')
-        .map(|x| x.parse::<usize>().unwrap())
-        .zip(candidates.into_iter().map(|x| x.trailing_zeros() as usize))
-        .filter_map(|(field, rule_idx)| {
-            if rules[rule_idx].name.starts_with("departure") {
-                Some(field)
+}
+
+impl std::error::Error for AssignmentError {}
+
+/// The largest matching of fields to distinct rules, found with
+/// Hopcroft-Karp. `candidates[field]` lists the rules the field may take.
+pub fn maximum_matching(candidates: &[Vec<usize>], num_rules: usize) -> Vec<Option<usize>> {
+    let mut matcher = Matcher {
+        candidates,
+        dist: vec![0; candidates.len()],
+        field_match: vec![None; candidates.len()],
+        rule_match: vec![None; num_rules],
+    };
+    while matcher.layer() {
+        for field in 0..candidates.len() {
+            if matcher.field_match[field].is_none() {
+                matcher.augment(field);
+            }
+        }
+    }
+    matcher.field_match
+}
+
+struct Matcher<'a> {
+    candidates: &'a [Vec<usize>],
+    dist: Vec<usize>,
+    field_match: Vec<Option<usize>>,
+    rule_match: Vec<Option<usize>>,
+}
+
+impl<'a> Matcher<'a> {
+    // Breadth first search from the unmatched fields, labelling each field
+    // with its distance along alternating paths. Returns whether an
+    // augmenting path exists.
+    fn layer(&mut self) -> bool {
+        let mut queue = std::collections::VecDeque::new();
+        for (field, dist) in self.dist.iter_mut().enumerate() {
+            *dist = if self.field_match[field].is_none() {
+                queue.push_back(field);
+                0
             } else {
-                None
+                usize::MAX
+            };
+        }
+        let mut found = false;
+        while let Some(field) = queue.pop_front() {
+            for &rule in &self.candidates[field] {
+                match self.rule_match[rule] {
+                    None => found = true,
+                    Some(next) if self.dist[next] == usize::MAX => {
+                        self.dist[next] = self.dist[field] + 1;
+                        queue.push_back(next);
+                    }
+                    Some(_) => {}
+                }
             }
-        })
+        }
+        found
+    }
+
+    // Depth first search along the layers for an augmenting path.
+    fn augment(&mut self, field: usize) -> bool {
+        let candidates = self.candidates;
+        for &rule in &candidates[field] {
+            let reachable = match self.rule_match[rule] {
+                None => true,
+                Some(next) => self.dist[next] == self.dist[field] + 1 && self.augment(next),
+            };
+            if reachable {
+                self.field_match[field] = Some(rule);
+                self.rule_match[rule] = Some(field);
+                return true;
+            }
+        }
+        self.dist[field] = usize::MAX;
+        false
+    }
+}
+
+/// Up to `limit` assignments of a distinct rule to every field. Each
+/// assignment lists the rule index for each field.
+pub fn assignments(candidates: &[Vec<usize>], num_rules: usize, limit: usize) -> Vec<Vec<usize>> {
+    let mut search = AssignmentSearch {
+        candidates,
+        assigned: vec![None; candidates.len()],
+        used: vec![false; num_rules],
+        limit,
+        results: Vec::new(),
+    };
+    search.search();
+    search.results
+}
+
+/// The only assignment of a distinct rule to every field.
+pub fn unique_assignment(
+    candidates: &[Vec<usize>],
+    num_rules: usize,
+) -> Result<Vec<usize>, AssignmentError> {
+    let mut found = assignments(candidates, num_rules, 2).into_iter();
+    match (found.next(), found.next()) {
+        (None, _) => Err(AssignmentError::Unsatisfiable),
+        (Some(assignment), None) => Ok(assignment),
+        (Some(first), Some(second)) => Err(AssignmentError::Ambiguous { first, second }),
+    }
+}
+
+struct AssignmentSearch<'a> {
+    candidates: &'a [Vec<usize>],
+    assigned: Vec<Option<usize>>,
+    used: Vec<bool>,
+    limit: usize,
+    results: Vec<Vec<usize>>,
+}
+
+impl<'a> AssignmentSearch<'a> {
+    // Backtrack over the most constrained field first, pruning any branch
+    // where the remaining fields can't all be matched.
+    fn search(&mut self) {
+        if self.results.len() >= self.limit {
+            return;
+        }
+        let remaining: Vec<Vec<usize>> = self
+            .candidates
+            .iter()
+            .zip(&self.assigned)
+            .map(|(rules, assigned)| match assigned {
+                Some(_) => Vec::new(),
+                None => rules.iter().copied().filter(|&r| !self.used[r]).collect(),
+            })
+            .collect();
+        let field = match (0..remaining.len())
+            .filter(|&field| self.assigned[field].is_none())
+            .min_by_key(|&field| remaining[field].len())
+        {
+            Some(field) => field,
+            None => {
+                self.results
+                    .push(self.assigned.iter().map(|r| r.unwrap()).collect());
+                return;
+            }
+        };
+        let unassigned = self.assigned.iter().filter(|r| r.is_none()).count();
+        let matched = maximum_matching(&remaining, self.used.len())
+            .iter()
+            .filter(|r| r.is_some())
+            .count();
+        if matched < unassigned {
+            return;
+        }
+        for &rule in &remaining[field] {
+            self.assigned[field] = Some(rule);
+            self.used[rule] = true;
+            self.search();
+            self.used[rule] = false;
+            self.assigned[field] = None;
+        }
+    }
+}
+
+#[aoc(day16, part1)]
+fn solve_d16_p1(input: &str) -> u64 {
+    Notes::parse(input).unwrap().error_rate()
+}
+
+fn departure_product(fields: &[(&str, u16)]) -> usize {
+    fields
+        .iter()
+        .filter(|(name, _)| name.starts_with("departure"))
+        .map(|&(_, value)| value as usize)
         .product()
 }
 
-#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
-#[aoc(day16, part2, avx2)]
-fn solve_d16_p2_avx2(input: &str) -> usize {
-    unsafe { avx2::solve_d16_p2(input) }
+#[aoc(day16, part2)]
+fn solve_d16_p2(input: &str) -> usize {
+    let notes = Notes::parse(input).unwrap();
+    departure_product(&notes.my_ticket_fields().unwrap())
 }
 
-#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
-mod avx2 {
-    use super::{split_once, Rule};
-    use std::arch::x86_64::*;
+#[aoc(day16, part2, simd)]
+fn solve_d16_p2_simd(input: &str) -> usize {
+    let notes = Notes::parse(input).unwrap();
+    let eval = RuleEval::new(&notes.rules);
+    let assignment = unique_assignment(&notes.candidates_with(&eval), notes.rules.len()).unwrap();
+    departure_product(&notes.label_my_ticket(&assignment))
+}
+
+/// The implementations `RuleEval` can use.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EvalKind {
+    Scalar,
+    Sse2,
+    Avx2,
+}
 
-    struct RuleEval {
-        lo_start: [__m256i; 2],
-        lo_end: [__m256i; 2],
-        hi_start: [__m256i; 2],
-        hi_end: [__m256i; 2],
+/// Checks a value against every rule at once. The result is a bitmap of
+/// 32 bit words where bit `i % 32` of word `i / 32` is set if rule `i`
+/// matches.
+pub struct RuleEval {
+    backend: Backend,
+    words: usize,
+}
+
+enum Backend {
+    Scalar(Vec<Vec<RangeInclusive<u16>>>),
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Sse2(simd::Sse2Eval),
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Avx2(simd::Avx2Eval),
+}
+
+impl RuleEval {
+    /// Use the fastest implementation the CPU supports.
+    pub fn new(rules: &[Rule]) -> Self {
+        [EvalKind::Avx2, EvalKind::Sse2, EvalKind::Scalar]
+            .iter()
+            .find_map(|&kind| RuleEval::with_kind(rules, kind))
+            .unwrap()
     }
 
-    impl RuleEval {
-        #[target_feature(enable = "avx2")]
-        unsafe fn new(rules: &[Rule]) -> Option<Self> {
-            if rules.len() > 31 {
-                return None;
+    /// Use a specific implementation. Returns `None` if the CPU doesn't
+    /// support it, or if it's a SIMD implementation and a range ends above
+    /// `i16::MAX - 1`, since the vector comparisons are signed.
+    pub fn with_kind(rules: &[Rule], kind: EvalKind) -> Option<Self> {
+        let backend = match kind {
+            EvalKind::Scalar => Backend::Scalar(rules.iter().map(|r| r.ranges.clone()).collect()),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            EvalKind::Sse2 if is_x86_feature_detected!("sse2") => {
+                Backend::Sse2(unsafe { simd::Sse2Eval::new(rules)? })
             }
-            let lo_start = {
-                let mut lo_start = [_mm256_setzero_si256(); 2];
-                let lo_start_16 = &mut *(&mut lo_start as *mut [__m256i; 2] as *mut [i16; 32]);
-                let mut rules_iter = rules.iter();
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    lo_start_16[idx] = *rule.a.start() as i16 - 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    lo_start_16[idx + 16] = *rule.a.start() as i16 - 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    lo_start_16[idx + 8] = *rule.a.start() as i16 - 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    lo_start_16[idx + 24] = *rule.a.start() as i16 - 1;
-                }
-                lo_start
-            };
-            let lo_end = {
-                let mut lo_end = [_mm256_setzero_si256(); 2];
-                let lo_end_16 = &mut *(&mut lo_end as *mut [__m256i; 2] as *mut [i16; 32]);
-                let mut rules_iter = rules.iter();
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    lo_end_16[idx] = *rule.a.end() as i16 + 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    lo_end_16[idx + 16] = *rule.a.end() as i16 + 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    lo_end_16[idx + 8] = *rule.a.end() as i16 + 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    lo_end_16[idx + 24] = *rule.a.end() as i16 + 1;
-                }
-                lo_end
-            };
-            let hi_start = {
-                let mut hi_start = [_mm256_setzero_si256(); 2];
-                let hi_start_16 = &mut *(&mut hi_start as *mut [__m256i; 2] as *mut [i16; 32]);
-                let mut rules_iter = rules.iter();
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    hi_start_16[idx] = *rule.b.start() as i16 - 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    hi_start_16[idx + 16] = *rule.b.start() as i16 - 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    hi_start_16[idx + 8] = *rule.b.start() as i16 - 1;
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            EvalKind::Avx2 if is_x86_feature_detected!("avx2") => {
+                Backend::Avx2(unsafe { simd::Avx2Eval::new(rules)? })
+            }
+            _ => return None,
+        };
+        Some(RuleEval {
+            backend,
+            words: rules.len().div_ceil(32),
+        })
+    }
+
+    pub fn kind(&self) -> EvalKind {
+        match self.backend {
+            Backend::Scalar(_) => EvalKind::Scalar,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Sse2(_) => EvalKind::Sse2,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Avx2(_) => EvalKind::Avx2,
+        }
+    }
+
+    /// The number of words in each bitmap.
+    pub fn words(&self) -> usize {
+        self.words
+    }
+
+    /// Write the bitmap of rules matching value into `out`, which must be
+    /// `words()` long.
+    pub fn eval(&self, value: u16, out: &mut [u32]) {
+        assert_eq!(out.len(), self.words);
+        match &self.backend {
+            Backend::Scalar(rules) => {
+                out.iter_mut().for_each(|word| *word = 0);
+                for (idx, ranges) in rules.iter().enumerate() {
+                    if ranges.iter().any(|range| range.contains(&value)) {
+                        out[idx / 32] |= 1 << (idx % 32);
+                    }
                 }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    hi_start_16[idx + 24] = *rule.b.start() as i16 - 1;
+            }
+            // The constructors checked the CPU supports these.
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Sse2(eval) => unsafe { eval.eval(value, out) },
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Avx2(eval) => unsafe { eval.eval(value, out) },
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod simd {
+    use super::Rule;
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    // The bounds of every rule as i16 lanes, laid out in chunks of `width`
+    // rules with one set of lanes per range. Starts are decremented and ends
+    // incremented so a value is in range when start < value < end. Rules
+    // with fewer ranges than the others get empty ranges that never match.
+    // `position` maps a rule's index within a chunk to its lane.
+    struct Lanes {
+        starts: Vec<i16>,
+        ends: Vec<i16>,
+        slots: usize,
+    }
+
+    impl Lanes {
+        fn new(rules: &[Rule], width: usize, position: impl Fn(usize) -> usize) -> Option<Self> {
+            let slots = rules
+                .iter()
+                .map(|r| r.ranges.len())
+                .max()
+                .unwrap_or(0)
+                .max(1);
+            let chunks = rules.len().div_ceil(width);
+            let mut starts = vec![i16::MAX; chunks * slots * width];
+            let mut ends = vec![i16::MIN; chunks * slots * width];
+            for (idx, rule) in rules.iter().enumerate() {
+                let (chunk, lane) = (idx / width, position(idx % width));
+                for (slot, range) in rule.ranges.iter().enumerate() {
+                    if *range.end() >= i16::MAX as u16 {
+                        return None;
+                    }
+                    let lane_idx = (chunk * slots + slot) * width + lane;
+                    starts[lane_idx] = *range.start() as i16 - 1;
+                    ends[lane_idx] = *range.end() as i16 + 1;
                 }
-                hi_start
+            }
+            Some(Lanes {
+                starts,
+                ends,
+                slots,
+            })
+        }
+    }
+
+    /// 16 rules per pair of 128 bit registers.
+    pub struct Sse2Eval {
+        starts: Vec<__m128i>,
+        ends: Vec<__m128i>,
+        slots: usize,
+    }
+
+    impl Sse2Eval {
+        #[target_feature(enable = "sse2")]
+        pub unsafe fn new(rules: &[Rule]) -> Option<Self> {
+            // _mm_packs_epi16 keeps the lanes in order.
+            let lanes = Lanes::new(rules, 16, |idx| idx)?;
+            let load = |v: &[i16]| {
+                v.chunks(8)
+                    .map(|c| _mm_loadu_si128(c.as_ptr() as *const __m128i))
+                    .collect()
             };
-            let hi_end = {
-                let mut hi_end = [_mm256_setzero_si256(); 2];
-                let hi_end_16 = &mut *(&mut hi_end as *mut [__m256i; 2] as *mut [i16; 32]);
-                let mut rules_iter = rules.iter();
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    hi_end_16[idx] = *rule.b.end() as i16 + 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    hi_end_16[idx + 16] = *rule.b.end() as i16 + 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    hi_end_16[idx + 8] = *rule.b.end() as i16 + 1;
-                }
-                for (idx, rule) in rules_iter.by_ref().take(8).enumerate() {
-                    hi_end_16[idx + 24] = *rule.b.end() as i16 + 1;
+            Some(Sse2Eval {
+                starts: load(&lanes.starts),
+                ends: load(&lanes.ends),
+                slots: lanes.slots,
+            })
+        }
+
+        #[target_feature(enable = "sse2")]
+        pub unsafe fn eval(&self, value: u16, out: &mut [u32]) {
+            let value = _mm_set1_epi16(value as i16);
+            let chunk_regs = 2 * self.slots;
+            let mut chunks = self
+                .starts
+                .chunks(chunk_regs)
+                .zip(self.ends.chunks(chunk_regs));
+            for word in out.iter_mut() {
+                *word = 0;
+                for half in 0..2 {
+                    let (starts, ends) = match chunks.next() {
+                        Some(chunk) => chunk,
+                        None => break,
+                    };
+                    let mut valid = [_mm_setzero_si128(); 2];
+                    for (reg, (&start, &end)) in starts.iter().zip(ends).enumerate() {
+                        let within = _mm_and_si128(
+                            _mm_cmpgt_epi16(end, value),
+                            _mm_cmpgt_epi16(value, start),
+                        );
+                        valid[reg % 2] = _mm_or_si128(valid[reg % 2], within);
+                    }
+                    let packed = _mm_packs_epi16(valid[0], valid[1]);
+                    *word |= (_mm_movemask_epi8(packed) as u32) << (16 * half);
                 }
-                hi_end
+            }
+        }
+    }
+
+    /// 32 rules per pair of 256 bit registers.
+    pub struct Avx2Eval {
+        starts: Vec<__m256i>,
+        ends: Vec<__m256i>,
+        slots: usize,
+    }
+
+    impl Avx2Eval {
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn new(rules: &[Rule]) -> Option<Self> {
+            // _mm256_packs_epi16 interleaves the 128 bit halves of its
+            // inputs, so rules 0-7 go in the low half of the first register,
+            // 8-15 in the low half of the second, 16-23 in the high half of
+            // the first and 24-31 in the high half of the second.
+            let lanes = Lanes::new(rules, 32, |idx| [0, 16, 8, 24][idx / 8] + idx % 8)?;
+            let load = |v: &[i16]| {
+                v.chunks(16)
+                    .map(|c| _mm256_loadu_si256(c.as_ptr() as *const __m256i))
+                    .collect()
             };
-            Some(RuleEval {
-                lo_start,
-                lo_end,
-                hi_start,
-                hi_end,
+            Some(Avx2Eval {
+                starts: load(&lanes.starts),
+                ends: load(&lanes.ends),
+                slots: lanes.slots,
             })
         }
 
         #[target_feature(enable = "avx2")]
-        unsafe fn eval(&self, value: u16) -> u32 {
+        pub unsafe fn eval(&self, value: u16, out: &mut [u32]) {
             let value = _mm256_set1_epi16(value as i16);
-            let within_lo = [
-                _mm256_and_si256(
-                    _mm256_cmpgt_epi16(self.lo_end[0], value),
-                    _mm256_cmpgt_epi16(value, self.lo_start[0]),
-                ),
-                _mm256_and_si256(
-                    _mm256_cmpgt_epi16(self.lo_end[1], value),
-                    _mm256_cmpgt_epi16(value, self.lo_start[1]),
-                ),
-            ];
-            let within_hi = [
-                _mm256_and_si256(
-                    _mm256_cmpgt_epi16(self.hi_end[0], value),
-                    _mm256_cmpgt_epi16(value, self.hi_start[0]),
-                ),
-                _mm256_and_si256(
-                    _mm256_cmpgt_epi16(self.hi_end[1], value),
-                    _mm256_cmpgt_epi16(value, self.hi_start[1]),
-                ),
-            ];
-            let valid = [
-                _mm256_or_si256(within_lo[0], within_hi[0]),
-                _mm256_or_si256(within_lo[1], within_hi[1]),
-            ];
-            let packed = _mm256_packs_epi16(valid[0], valid[1]);
-            _mm256_movemask_epi8(packed) as u32
+            let chunk_regs = 2 * self.slots;
+            let chunks = self
+                .starts
+                .chunks(chunk_regs)
+                .zip(self.ends.chunks(chunk_regs));
+            for (word, (starts, ends)) in out.iter_mut().zip(chunks) {
+                let mut valid = [_mm256_setzero_si256(); 2];
+                for (reg, (&start, &end)) in starts.iter().zip(ends).enumerate() {
+                    let within = _mm256_and_si256(
+                        _mm256_cmpgt_epi16(end, value),
+                        _mm256_cmpgt_epi16(value, start),
+                    );
+                    valid[reg % 2] = _mm256_or_si256(valid[reg % 2], within);
+                }
+                let packed = _mm256_packs_epi16(valid[0], valid[1]);
+                *word = _mm256_movemask_epi8(packed) as u32;
+            }
         }
     }
+}
 
-    #[target_feature(enable = "avx2")]
-    pub unsafe fn solve_d16_p2(input: &str) -> usize {
-        let (rules, rem) = split_once(input, "\n\nyour ticket:\n").unwrap();
-        let (my_ticket, nearby_tickets) = split_once(rem, "\n\nnearby tickets:\n").unwrap();
-
-        let rules: Vec<_> = rules.split('\n').map(|x| Rule::parse(x).unwrap()).collect();
-        assert!(rules.len() < 32);
-        let rule_eval = RuleEval::new(&rules).unwrap();
-        let mut candidates = [_mm256_set1_epi32((1i32 << rules.len()) - 1); 4];
-        let mut scratch_space = [_mm256_set1_epi32(1); 4];
-        for line in nearby_tickets.split('\n') {
-            let scratch_slice = &mut *(&mut scratch_space as *mut _ as *mut [u32; 32]);
-            for (field, scratch) in line.split(',').zip(scratch_slice.iter_mut()) {
-                let field = field.parse().unwrap();
-                *scratch = rule_eval.eval(field);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xorshift;
 
-            if scratch_space.iter().copied().any(|elem| {
-                _mm256_movemask_epi8(_mm256_cmpeq_epi32(elem, _mm256_set1_epi32(0))) != 0
-            }) {
-                continue;
-            }
+    #[test]
+    fn test_part1() {
+        let input = "class: 1-3 or 5-7\nrow: 6-11 or 33-44\nseat: 13-40 or 45-50\n\n\
+                     your ticket:\n7,1,14\n\n\
+                     nearby tickets:\n7,3,47\n40,4,50\n55,2,20\n38,6,12";
+        assert_eq!(solve_d16_p1(input), 71);
+    }
 
-            for (candidate, valid_bitmask) in candidates.iter_mut().zip(scratch_space.iter()) {
-                *candidate = _mm256_and_si256(*candidate, *valid_bitmask);
-            }
-        }
+    #[test]
+    fn test_scan() {
+        let input = "class: 1-3 or 5-7 or 60-61\nrow: 6-11\n\n\
+                     your ticket:\n7,1\n\n\
+                     nearby tickets:\n7,3\n4,60\n55,12";
+        let notes = Notes::parse(input).unwrap();
+        assert_eq!(notes.rules()[0].ranges, vec![1..=3, 5..=7, 60..=61]);
+        let report = notes.scan();
+        assert_eq!(report.error_rate(), 4 + 55 + 12);
+        assert_eq!(notes.error_rate(), report.error_rate());
+        assert_eq!(report.valid_tickets().count(), 1);
+        assert_eq!(report.tickets[0].values[0].satisfied, vec![0, 1]);
+        let invalid: Vec<_> = report.tickets[2]
+            .invalid_values()
+            .map(|v| v.field)
+            .collect();
+        assert_eq!(invalid, vec![0, 1]);
+        assert_eq!(report.tickets[1].values[1].satisfied, vec![0]);
+        assert_eq!(notes.candidates(), vec![vec![0, 1], vec![0]]);
+    }
 
-        let candidates = &mut *(&mut candidates as *mut _ as *mut [u32; 32]);
-        let candidates = &mut candidates[..rules.len()];
-        while candidates.iter().copied().any(|x| x.count_ones() > 1) {
-            for idx in 0..candidates.len() {
-                let candidate = candidates[idx];
-                if candidate.count_ones() == 1 {
-                    let mask = !candidate;
-                    for before in &mut candidates[..idx] {
-                        *before &= mask;
-                    }
-                    for after in &mut candidates[idx + 1..] {
-                        *after &= mask;
-                    }
-                }
+    #[test]
+    fn test_my_ticket_fields() {
+        let input = "class: 0-1 or 4-19\nrow: 0-5 or 8-19\nseat: 0-13 or 16-19\n\n\
+                     your ticket:\n11,12,13\n\n\
+                     nearby tickets:\n3,9,18\n15,1,5\n5,14,9";
+        let notes = Notes::parse(input).unwrap();
+        assert_eq!(
+            notes.my_ticket_fields(),
+            Ok(vec![("row", 11), ("class", 12), ("seat", 13)])
+        );
+        let eval = RuleEval::new(notes.rules());
+        assert_eq!(notes.candidates_with(&eval), notes.candidates());
+    }
+
+    #[test]
+    fn test_rule_eval() {
+        let mut next = xorshift(0x853c_49e6_748f_ea9b);
+        let mut rng = move |bound: u64| next() % bound;
+        // More rules than fit in one word, with varying numbers of ranges.
+        let rules: Vec<Rule> = (0..70)
+            .map(|_| Rule {
+                name: "rule",
+                ranges: (0..1 + rng(3))
+                    .map(|_| {
+                        let start = rng(1000) as u16;
+                        start..=start + rng(100) as u16
+                    })
+                    .collect(),
+            })
+            .collect();
+        let scalar = RuleEval::with_kind(&rules, EvalKind::Scalar).unwrap();
+        let best = RuleEval::new(&rules);
+        assert_eq!(best.words(), 3);
+        let mut expected = vec![0; 3];
+        let mut actual = vec![0; 3];
+        for kind in [EvalKind::Sse2, EvalKind::Avx2, best.kind()] {
+            let eval = match RuleEval::with_kind(&rules, kind) {
+                Some(eval) => eval,
+                None => continue,
+            };
+            for value in (0..1200).chain(vec![32767, 40000, u16::MAX]) {
+                scalar.eval(value, &mut expected);
+                eval.eval(value, &mut actual);
+                assert_eq!(actual, expected, "{:?} value {}", kind, value);
             }
         }
-        my_ticket
-            .split(',')
-            .map(|x| x.parse::<usize>().unwrap())
-            .zip(candidates.into_iter().map(|x| x.trailing_zeros() as usize))
-            .filter_map(|(field, rule_idx)| {
-                if rules[rule_idx].name.starts_with("departure") {
-                    Some(field)
-                } else {
-                    None
-                }
+
+        let large = vec![Rule::parse("big: 1-2 or 30000-40000").unwrap()];
+        assert!(RuleEval::with_kind(&large, EvalKind::Avx2).is_none());
+        assert_eq!(RuleEval::new(&large).kind(), EvalKind::Scalar);
+    }
+
+    #[test]
+    fn test_assignments() {
+        assert_eq!(
+            unique_assignment(&[vec![0], vec![0]], 2),
+            Err(AssignmentError::Unsatisfiable)
+        );
+        assert_eq!(
+            unique_assignment(&[vec![0, 1], vec![0, 1], vec![2]], 3),
+            Err(AssignmentError::Ambiguous {
+                first: vec![0, 1, 2],
+                second: vec![1, 0, 2]
             })
-            .product()
+        );
+        assert_eq!(assignments(&[vec![0, 1, 2], vec![0, 1, 2]], 3, 10).len(), 6);
+
+        // Far more rules than fit in a u64 bitmap.
+        let candidates: Vec<Vec<usize>> = (0..100).map(|field| (field..100).collect()).collect();
+        assert_eq!(
+            unique_assignment(&candidates, 100),
+            Ok((0..100).collect::<Vec<_>>())
+        );
+        let matching = maximum_matching(&candidates, 100);
+        assert!(matching.iter().all(|rule| rule.is_some()));
     }
 }
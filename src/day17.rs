@@ -1,101 +1,307 @@
+use crate::automaton::Lattice;
 use aoc_runner_derive::aoc;
-use std::collections::HashSet;
 
-#[aoc(day17, part1)]
-fn solve_d17_p1(input: &str) -> usize {
-    let mut world = HashSet::new();
-    for (y, line) in input.split('\n').enumerate() {
-        for (x, _) in line
-            .as_bytes()
-            .iter()
-            .copied()
-            .enumerate()
-            .filter(|&(_, byte)| byte == b'#')
-        {
-            world.insert((x as isize, y as isize, 0isize, 0isize));
-        }
-    }
-    for _ in 0..6 {
-        world = step(world);
-    }
-    world.len()
+/// The active cubes of an N dimensional pocket dimension that starts as a
+/// 2-D slice. Every dimension after the first two starts out at 0, so the
+/// state stays symmetric under negating any of them and only cubes whose
+/// extra coordinates are all non-negative need to be stored.
+///
+/// Cubes are stored in a flat array covering every cube that can become
+/// active within the next few cycles, plus a border of one so neighbor
+/// counts never fall outside it. The array is rebuilt with a larger
+/// region when the simulation runs past that point.
+#[derive(Debug, Clone)]
+pub struct PocketDimension<const N: usize> {
+    // The coordinates of index 0 and the size of the region in each
+    // dimension. The first dimension has stride 1.
+    origin: [i32; N],
+    shape: [usize; N],
+    strides: [usize; N],
+    cells: Vec<bool>,
+    active: Vec<[i32; N]>,
+    // Weighted neighbor counts, and the indices with a non-zero count.
+    sums: Vec<u32>,
+    touched: Vec<usize>,
+    // Neighbor offsets and the matching index deltas.
+    offsets: Vec<[i32; N]>,
+    deltas: Vec<isize>,
+    cycles: usize,
+    // The number of cycles the region is large enough for.
+    capacity: usize,
 }
 
-fn neighbors(
-    world: &HashSet<(isize, isize, isize, isize)>,
-    (x, y, z, w): (isize, isize, isize, isize),
-) -> usize {
-    let mut count = 0;
-    for xa in x - 1..=x + 1 {
-        for ya in y - 1..=y + 1 {
-            for za in z - 1..=z + 1 {
-                for wa in w - 1..=w + 1 {
-                    count += world.contains(&(xa, ya, za, wa)) as usize;
+impl<const N: usize> PocketDimension<N> {
+    /// The initial region is large enough for the 6 cycle boot process.
+    const INITIAL_CYCLES: usize = 6;
+
+    /// Parse the initial slice of `#` (active) and `.` (inactive) cubes.
+    pub fn parse(input: &str) -> Option<Self> {
+        assert!(N >= 2, "the initial slice needs at least 2 dimensions");
+        let mut active = Vec::new();
+        for (y, line) in input.lines().enumerate() {
+            for (x, byte) in line.bytes().enumerate() {
+                match byte {
+                    b'#' => {
+                        let mut cube = [0; N];
+                        cube[0] = x as i32;
+                        cube[1] = y as i32;
+                        active.push(cube);
+                    }
+                    b'.' => {}
+                    _ => return None,
                 }
             }
         }
+        let offsets = Lattice::<N>::offsets()
+            .map(|offset| offset.map(|d| d as i32))
+            .collect();
+        let mut dimension = PocketDimension {
+            origin: [0; N],
+            shape: [0; N],
+            strides: [0; N],
+            cells: Vec::new(),
+            active,
+            sums: Vec::new(),
+            touched: Vec::new(),
+            offsets,
+            deltas: Vec::new(),
+            cycles: 0,
+            capacity: 0,
+        };
+        dimension.reserve(Self::INITIAL_CYCLES);
+        Some(dimension)
     }
-    count - world.contains(&(x, y, z, w)) as usize
-}
 
-fn step(world: HashSet<(isize, isize, isize, isize)>) -> HashSet<(isize, isize, isize, isize)> {
-    struct MinMax {
-        x_min: isize,
-        x_max: isize,
-        y_min: isize,
-        y_max: isize,
-        z_min: isize,
-        z_max: isize,
-        w_min: isize,
-        w_max: isize,
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    pub fn is_active(&self, cube: [i32; N]) -> bool {
+        self.index(canonical(cube))
+            .is_some_and(|idx| self.cells[idx])
+    }
+
+    /// The number of active cubes, including every mirror image.
+    pub fn active_count(&self) -> usize {
+        self.active
+            .iter()
+            .map(|cube| 1 << (N - 2 - zeros(cube)))
+            .sum()
+    }
+
+    /// Make the region large enough for `cycles` more cycles.
+    pub fn reserve(&mut self, cycles: usize) {
+        if self.cycles + cycles <= self.capacity {
+            return;
+        }
+        let reach = cycles as i32 + 1;
+        let mut lo = [0; N];
+        let mut hi = [0; N];
+        if let Some(first) = self.active.first() {
+            lo = *first;
+            hi = *first;
+        }
+        for cube in &self.active {
+            for dim in 0..N {
+                lo[dim] = lo[dim].min(cube[dim]);
+                hi[dim] = hi[dim].max(cube[dim]);
+            }
+        }
+        let mut stride = 1;
+        for dim in 0..N {
+            // Only the non-negative side of the mirrored dimensions is stored.
+            self.origin[dim] = if dim < 2 { lo[dim] - reach } else { 0 };
+            self.shape[dim] = (hi[dim] + reach - self.origin[dim] + 1) as usize;
+            self.strides[dim] = stride;
+            stride *= self.shape[dim];
+        }
+        self.cells = vec![false; stride];
+        self.sums = vec![0; stride];
+        for idx in 0..self.active.len() {
+            let idx = self.index(self.active[idx]).unwrap();
+            self.cells[idx] = true;
+        }
+        self.deltas = self
+            .offsets
+            .iter()
+            .map(|offset| {
+                (0..N)
+                    .map(|dim| offset[dim] as isize * self.strides[dim] as isize)
+                    .sum()
+            })
+            .collect();
+        self.capacity = self.cycles + cycles;
     }
-    let MinMax {
-        x_min,
-        x_max,
-        y_min,
-        y_max,
-        z_min,
-        z_max,
-        w_min,
-        w_max,
-    } = world.iter().fold(
-        MinMax {
-            x_min: isize::MAX,
-            x_max: isize::MIN,
-            y_min: isize::MAX,
-            y_max: isize::MIN,
-            z_min: isize::MAX,
-            z_max: isize::MIN,
-            w_min: isize::MAX,
-            w_max: isize::MIN,
-        },
-        |minmax, &(x, y, z, w)| MinMax {
-            x_min: std::cmp::min(minmax.x_min, x),
-            x_max: std::cmp::max(minmax.x_max, x),
-            y_min: std::cmp::min(minmax.y_min, y),
-            y_max: std::cmp::max(minmax.y_max, y),
-            z_min: std::cmp::min(minmax.z_min, z),
-            z_max: std::cmp::max(minmax.z_max, z),
-            w_min: std::cmp::min(minmax.w_min, w),
-            w_max: std::cmp::max(minmax.w_max, w),
-        },
-    );
-    let mut new_world = HashSet::new();
-    for x in x_min - 1..=x_max + 1 {
-        for y in y_min - 1..=y_max + 1 {
-            for z in z_min - 1..=z_max + 1 {
-                for w in w_min - 1..=w_max + 1 {
-                    let active = world.contains(&(x, y, z, w));
-                    let n = neighbors(&world, (x, y, z, w));
-                    if active && (n == 2 || n == 3) {
-                        new_world.insert((x, y, z, w));
+
+    pub fn step(&mut self) {
+        if self.cycles >= self.capacity {
+            self.reserve(self.capacity.max(Self::INITIAL_CYCLES));
+        }
+        // Scatter from each stored active cube to the stored cubes around
+        // it. A stored cube stands for 2^(mirrored dimensions where it isn't
+        // 0) cubes, and the neighbor relation between stored cubes is
+        // lopsided across a mirror, so each count is weighted by the
+        // source's images and later divided by the destination's.
+        let extra = N - 2;
+        let mut touched = std::mem::take(&mut self.touched);
+        for &cube in &self.active {
+            let cube_zeros = zeros(&cube);
+            let weight = 1 << (extra - cube_zeros);
+            if cube_zeros == 0 {
+                // No neighbor crosses a mirror, so the deltas can be used.
+                let idx = self.index(cube).unwrap() as isize;
+                for &delta in &self.deltas {
+                    let neighbor = (idx + delta) as usize;
+                    if self.sums[neighbor] == 0 {
+                        touched.push(neighbor);
                     }
-                    if !active && n == 3 {
-                        new_world.insert((x, y, z, w));
+                    self.sums[neighbor] += weight;
+                }
+            } else {
+                for offset in &self.offsets {
+                    let mut neighbor = cube;
+                    for dim in 0..N {
+                        neighbor[dim] += offset[dim];
                     }
+                    let neighbor = self.index(canonical(neighbor)).unwrap();
+                    if self.sums[neighbor] == 0 {
+                        touched.push(neighbor);
+                    }
+                    self.sums[neighbor] += weight;
                 }
             }
         }
+
+        let mut next = Vec::with_capacity(self.active.len());
+        for &idx in &touched {
+            let cube = self.coords(idx);
+            let active_neighbors = self.sums[idx] >> (extra - zeros(&cube));
+            self.sums[idx] = 0;
+            if active_neighbors == 3 || (active_neighbors == 2 && self.cells[idx]) {
+                next.push(cube);
+            }
+        }
+        touched.clear();
+        self.touched = touched;
+
+        let previous = std::mem::replace(&mut self.active, next);
+        for &cube in &previous {
+            let idx = self.index(cube).unwrap();
+            self.cells[idx] = false;
+        }
+        for idx in 0..self.active.len() {
+            let idx = self.index(self.active[idx]).unwrap();
+            self.cells[idx] = true;
+        }
+        self.cycles += 1;
+    }
+
+    pub fn step_n(&mut self, cycles: usize) {
+        self.reserve(cycles);
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+
+    fn index(&self, cube: [i32; N]) -> Option<usize> {
+        let mut idx = 0;
+        for (dim, &coord) in cube.iter().enumerate() {
+            let offset = coord - self.origin[dim];
+            if offset < 0 || offset as usize >= self.shape[dim] {
+                return None;
+            }
+            idx += offset as usize * self.strides[dim];
+        }
+        Some(idx)
+    }
+
+    fn coords(&self, idx: usize) -> [i32; N] {
+        let mut cube = self.origin;
+        for (dim, coord) in cube.iter_mut().enumerate() {
+            *coord += ((idx / self.strides[dim]) % self.shape[dim]) as i32;
+        }
+        cube
+    }
+}
+
+// The stored representative of a cube and its reflections.
+fn canonical<const N: usize>(mut cube: [i32; N]) -> [i32; N] {
+    for coord in &mut cube[2..] {
+        *coord = coord.abs();
+    }
+    cube
+}
+
+// The number of mirrored dimensions where the cube is at 0.
+fn zeros<const N: usize>(cube: &[i32; N]) -> usize {
+    cube[2..].iter().filter(|&&coord| coord == 0).count()
+}
+
+fn active_after_boot<const N: usize>(input: &str) -> usize {
+    let mut dimension = PocketDimension::<N>::parse(input).unwrap();
+    dimension.step_n(6);
+    dimension.active_count()
+}
+
+#[aoc(day17, part1)]
+fn solve_d17_p1(input: &str) -> usize {
+    active_after_boot::<3>(input)
+}
+
+#[aoc(day17, part2)]
+fn solve_d17_p2(input: &str) -> usize {
+    active_after_boot::<4>(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automaton::{Automaton, Rules};
+
+    const EXAMPLE: &str = ".#.\n..#\n###";
+
+    #[test]
+    fn test_example() {
+        assert_eq!(solve_d17_p1(EXAMPLE), 112);
+        assert_eq!(solve_d17_p2(EXAMPLE), 848);
+    }
+
+    #[test]
+    fn test_mirror_symmetry() {
+        // Compare against simulating every cube.
+        let active = [
+            [1, 0, 0, 0, 0],
+            [2, 1, 0, 0, 0],
+            [0, 2, 0, 0, 0],
+            [1, 2, 0, 0, 0],
+            [2, 2, 0, 0, 0],
+        ];
+        let mut full = Automaton::new(Lattice::<5>, Rules::conway(), active.iter().copied());
+        let mut mirrored = PocketDimension::<5>::parse(EXAMPLE).unwrap();
+        for _ in 0..3 {
+            full.step();
+            mirrored.step();
+            assert_eq!(mirrored.active_count(), full.population());
+            assert!(full
+                .live_cells()
+                .iter()
+                .all(|&cube| mirrored.is_active(cube.map(|c| c as i32))));
+        }
+        assert_eq!(mirrored.cycles(), 3);
+    }
+
+    #[test]
+    fn test_growth() {
+        // Run well past the initial region.
+        let active = [[1, 0, 0], [2, 1, 0], [0, 2, 0], [1, 2, 0], [2, 2, 0]];
+        let mut full = Automaton::new(Lattice::<3>, Rules::conway(), active.iter().copied());
+        let mut mirrored = PocketDimension::<3>::parse(EXAMPLE).unwrap();
+        full.step_n(15);
+        mirrored.step_n(15);
+        assert_eq!(mirrored.active_count(), full.population());
+        assert!(full
+            .live_cells()
+            .iter()
+            .all(|&cube| mirrored.is_active(cube.map(|c| c as i32))));
     }
-    new_world
 }
@@ -1,164 +1,590 @@
 use aoc_runner_derive::aoc;
 
-fn num(i: &str) -> Option<(&str, usize)> {
-    let i = i.trim_start();
-    let end_idx = i
-        .as_bytes()
-        .iter()
-        .copied()
-        .position(|b| !(b'0'..=b'9').contains(&b))
-        .unwrap_or(i.len());
-    let n = (&i[..end_idx]).parse().ok()?;
-    let rem = &i[end_idx..];
-    Some((rem, n))
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
 }
 
-#[aoc(day18, part1)]
-fn solve_d18_p1(input: &str) -> usize {
-    #[derive(Debug, Copy, Clone)]
-    enum Operator {
-        Add,
-        Mul,
-    }
-
-    // The next token is an operator '+' or '*'
-    fn operator(i: &str) -> Option<(&str, Operator)> {
-        let i = i.trim_start();
-        let op = match i.as_bytes()[0] {
-            b'+' => Operator::Add,
-            b'*' => Operator::Mul,
-            _ => return None,
-        };
-        Some((&i[1..], op))
+impl BinOp {
+    const ALL: [BinOp; 5] = [BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::Div, BinOp::Pow];
+
+    pub fn symbol(self) -> char {
+        match self {
+            BinOp::Add => '+',
+            BinOp::Sub => '-',
+            BinOp::Mul => '*',
+            BinOp::Div => '/',
+            BinOp::Pow => '^',
+        }
+    }
+
+    fn from_symbol(symbol: u8) -> Option<Self> {
+        BinOp::ALL
+            .iter()
+            .copied()
+            .find(|op| op.symbol() as u8 == symbol)
     }
 
-    // The next token is '('. Evaluate the entire expression within the parens.
-    fn paren(i: &str) -> Option<(&str, usize)> {
-        let i = i.trim_start();
-        if i.is_empty() || i.as_bytes()[0] != b'(' {
-            return None;
+    /// Apply the operator, returning `None` on overflow, division by zero or
+    /// a negative exponent.
+    fn apply(self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            BinOp::Add => lhs.checked_add(rhs),
+            BinOp::Sub => lhs.checked_sub(rhs),
+            BinOp::Mul => lhs.checked_mul(rhs),
+            BinOp::Div => lhs.checked_div(rhs),
+            BinOp::Pow => lhs.checked_pow(u32::try_from(rhs).ok()?),
         }
-        let (rem, n) = expr(&i[1..])?;
-        if rem.is_empty() || rem.as_bytes()[0] != b')' {
-            return None;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Token {
+    Num(i64),
+    Op(BinOp),
+    LParen,
+    RParen,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedChar {
+        pos: usize,
+        ch: char,
+    },
+    NumberTooLarge {
+        pos: usize,
+    },
+    UnexpectedToken {
+        pos: usize,
+        token: Token,
+    },
+    UnexpectedEnd,
+    /// The operator isn't in the operator table.
+    UnsupportedOperator {
+        pos: usize,
+        op: BinOp,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedChar { pos, ch } => {
+                write!(f, "unexpected character {:?} at {}", ch, pos)
+            }
+            ParseError::NumberTooLarge { pos } => write!(f, "number at {} is too large", pos),
+            ParseError::UnexpectedToken { pos, token } => {
+                write!(f, "unexpected {:?} at {}", token, pos)
+            }
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            ParseError::UnsupportedOperator { pos, op } => {
+                write!(f, "operator {} at {} is not supported", op.symbol(), pos)
+            }
         }
-        let rem = &rem[1..];
-        Some((rem, n))
     }
+}
 
-    // the next token is either a bare number or an expression within a paren,
-    // return either the number of the evaluation of the paren enclosed
-    // expression.
-    fn num_or_paren(i: &str) -> Option<(&str, usize)> {
-        num(i).or_else(|| paren(i))
+impl std::error::Error for ParseError {}
+
+/// Split an expression into tokens, each paired with its byte offset.
+pub fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let token = match bytes[pos] {
+            b' ' | b'\t' => {
+                pos += 1;
+                continue;
+            }
+            b'(' => Token::LParen,
+            b')' => Token::RParen,
+            b'0'..=b'9' => {
+                let len = bytes[pos..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_digit())
+                    .count();
+                let n = input[pos..pos + len]
+                    .parse()
+                    .map_err(|_| ParseError::NumberTooLarge { pos })?;
+                tokens.push((pos, Token::Num(n)));
+                pos += len;
+                continue;
+            }
+            b => match BinOp::from_symbol(b) {
+                Some(op) => Token::Op(op),
+                None => {
+                    return Err(ParseError::UnexpectedChar {
+                        pos,
+                        ch: input[pos..].chars().next().unwrap(),
+                    })
+                }
+            },
+        };
+        tokens.push((pos, token));
+        pos += 1;
     }
+    Ok(tokens)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
 
-    // the next token is an operator ('+' or '*') followed by a number or an
-    // expression within a paren.
-    fn operator_and_rhs(i: &str) -> Option<(&str, (Operator, usize))> {
-        let (i, op) = operator(i)?;
-        let (i, rhs) = num_or_paren(i)?;
-        Some((i, (op, rhs)))
+/// The precedence and associativity of each supported operator. Operators
+/// with higher precedence bind tighter.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct OperatorTable {
+    entries: [Option<(u8, Assoc)>; 5],
+}
+
+impl OperatorTable {
+    /// A table that supports no operators.
+    pub fn new() -> Self {
+        OperatorTable::default()
     }
 
-    // evaluate the expression provided as input. Return the remaining input
-    // after evaluation is complete.
-    fn expr(i: &str) -> Option<(&str, usize)> {
-        let (mut rem, mut lhs) = num_or_paren(i)?;
+    pub fn with(mut self, op: BinOp, precedence: u8, assoc: Assoc) -> Self {
+        self.entries[op as usize] = Some((precedence, assoc));
+        self
+    }
 
-        loop {
-            if rem.is_empty() {
-                break;
-            }
+    pub fn get(&self, op: BinOp) -> Option<(u8, Assoc)> {
+        self.entries[op as usize]
+    }
 
-            if let Some((irem, (op, rhs))) = operator_and_rhs(rem) {
-                rem = irem;
-                lhs = match op {
-                    Operator::Add => lhs + rhs,
-                    Operator::Mul => lhs * rhs,
-                };
-            } else {
-                break;
+    /// Part 1: `+`, `-`, `*` and `/` are evaluated left to right with equal
+    /// precedence. `^` binds tighter and is right associative.
+    pub fn same_precedence() -> Self {
+        OperatorTable::new()
+            .with(BinOp::Add, 1, Assoc::Left)
+            .with(BinOp::Sub, 1, Assoc::Left)
+            .with(BinOp::Mul, 1, Assoc::Left)
+            .with(BinOp::Div, 1, Assoc::Left)
+            .with(BinOp::Pow, 2, Assoc::Right)
+    }
+
+    /// Part 2: `+` and `-` bind tighter than `*` and `/`.
+    pub fn addition_first() -> Self {
+        OperatorTable::new()
+            .with(BinOp::Add, 2, Assoc::Left)
+            .with(BinOp::Sub, 2, Assoc::Left)
+            .with(BinOp::Mul, 1, Assoc::Left)
+            .with(BinOp::Div, 1, Assoc::Left)
+            .with(BinOp::Pow, 3, Assoc::Right)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// The operation overflowed, divided by zero or raised to a negative
+    /// power.
+    InvalidOperation { op: BinOp, lhs: i64, rhs: i64 },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::InvalidOperation { op, lhs, rhs } => {
+                write!(f, "cannot evaluate {} {} {}", lhs, op.symbol(), rhs)
             }
         }
-        Some((rem, lhs))
     }
+}
+
+impl std::error::Error for EvalError {}
 
-    input.split('\n').map(|line| expr(line).unwrap().1).sum()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(i64),
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
 }
 
-#[aoc(day18, part2)]
-fn solve_d18_p2(input: &str) -> usize {
-    // The next token is '('. Evaluate the entire expression within the parens.
-    fn paren(i: &str) -> Option<(&str, usize)> {
-        let i = i.trim_start();
-        if i.is_empty() || i.as_bytes()[0] != b'(' {
-            return None;
+impl Expr {
+    pub fn parse(input: &str, table: &OperatorTable) -> Result<Expr, ParseError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            idx: 0,
+            table,
+        };
+        let expr = parser.expr(0)?;
+        match parser.tokens.get(parser.idx) {
+            None => Ok(expr),
+            Some(&(pos, token)) => Err(ParseError::UnexpectedToken { pos, token }),
         }
-        let (rem, n) = expr(&i[1..])?;
-        if rem.is_empty() || rem.as_bytes()[0] != b')' {
-            return None;
+    }
+
+    /// Show the expression with only the parentheses needed to keep its
+    /// structure when parsed with `table`. Expressions that aren't shown
+    /// with a table are fully parenthesized.
+    pub fn display_with<'a>(&'a self, table: &'a OperatorTable) -> ExprDisplay<'a> {
+        ExprDisplay {
+            expr: self,
+            table: Some(table),
         }
-        let rem = &rem[1..];
-        Some((rem, n))
     }
 
-    // the next token is either a bare number or an expression within a paren,
-    // return either the number of the evaluation of the paren enclosed
-    // expression.
-    fn num_or_paren(i: &str) -> Option<(&str, usize)> {
-        num(i).or_else(|| paren(i))
+    /// Parse `input` with `table` and show every operation in parentheses,
+    /// except the outermost one, to make the grouping the table picks
+    /// explicit.
+    pub fn parenthesized(input: &str, table: &OperatorTable) -> Result<String, ParseError> {
+        Ok(Expr::parse(input, table)?.to_string())
     }
 
-    // The next token is a number or an expression within a paren, optionally
-    // followed by some number of '+' and number or paren enclosed expressions.
-    // The returned value is the sum of the entire sequence.
-    fn add_or_paren(i: &str) -> Option<(&str, usize)> {
-        let (mut i, mut lhs) = num_or_paren(i)?;
+    /// Replace the first operation whose operands are both numbers with its
+    /// result, working left to right. Returns false if the expression is
+    /// already a number.
+    pub fn reduce_step(&mut self) -> Result<bool, EvalError> {
+        let (op, lhs, rhs) = match self {
+            Expr::Num(_) => return Ok(false),
+            Expr::Binary { op, lhs, rhs } => (*op, lhs, rhs),
+        };
+        if let (Expr::Num(l), Expr::Num(r)) = (lhs.as_ref(), rhs.as_ref()) {
+            let (lhs, rhs) = (*l, *r);
+            let value = op
+                .apply(lhs, rhs)
+                .ok_or(EvalError::InvalidOperation { op, lhs, rhs })?;
+            *self = Expr::Num(value);
+            return Ok(true);
+        }
+        Ok(lhs.reduce_step()? || rhs.reduce_step()?)
+    }
 
-        loop {
-            i = i.trim_start();
-            if i.is_empty() || i.as_bytes()[0] != b'+' {
-                break;
+    /// The expression after each reduction step, starting with the
+    /// expression itself and ending with its value, shown as `display_with`
+    /// would.
+    pub fn trace(&self, table: &OperatorTable) -> Result<Vec<String>, EvalError> {
+        let mut expr = self.clone();
+        let mut lines = vec![expr.display_with(table).to_string()];
+        while expr.reduce_step()? {
+            lines.push(expr.display_with(table).to_string());
+        }
+        Ok(lines)
+    }
+
+    pub fn eval(&self) -> Result<i64, EvalError> {
+        match self {
+            Expr::Num(n) => Ok(*n),
+            Expr::Binary { op, lhs, rhs } => {
+                let (lhs, rhs) = (lhs.eval()?, rhs.eval()?);
+                op.apply(lhs, rhs)
+                    .ok_or(EvalError::InvalidOperation { op: *op, lhs, rhs })
             }
-            i = &i[1..];
-            if let Some((rem, rhs)) = num_or_paren(i) {
-                lhs += rhs;
-                i = rem;
-            } else {
-                break;
+        }
+    }
+}
+
+/// Shows every operation in parentheses, except the outermost one.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        ExprDisplay {
+            expr: self,
+            table: None,
+        }
+        .fmt(f)
+    }
+}
+
+/// See `Expr::display_with`.
+pub struct ExprDisplay<'a> {
+    expr: &'a Expr,
+    table: Option<&'a OperatorTable>,
+}
+
+impl<'a> ExprDisplay<'a> {
+    fn needs_parens(&self, child: &Expr, parent: BinOp, is_rhs: bool) -> bool {
+        let child = match child {
+            Expr::Num(_) => return false,
+            Expr::Binary { op, .. } => *op,
+        };
+        let table = match self.table {
+            Some(table) => table,
+            None => return true,
+        };
+        match (table.get(child), table.get(parent)) {
+            (Some((child_prec, _)), Some((parent_prec, assoc))) => {
+                child_prec < parent_prec
+                    || child_prec == parent_prec && is_rhs == (assoc == Assoc::Left)
             }
+            _ => true,
         }
-        Some((i, lhs))
     }
 
-    // Evaluate the expression.
-    fn expr(i: &str) -> Option<(&str, usize)> {
-        // add_or_paren will evaluate any consecutive elements of the expression
-        // that are separated by '+'. This enforces that '+' has a higher order
-        // of operation than '*'.
-        let (mut i, mut lhs) = add_or_paren(i)?;
+    fn write(&self, expr: &Expr, f: &mut fmt::Formatter) -> fmt::Result {
+        match expr {
+            Expr::Num(n) => write!(f, "{}", n),
+            Expr::Binary { op, lhs, rhs } => {
+                self.write_operand(lhs, *op, false, f)?;
+                write!(f, " {} ", op.symbol())?;
+                self.write_operand(rhs, *op, true, f)
+            }
+        }
+    }
 
-        loop {
-            // The next token is expected to be '*'. Remember that all '+'
-            // operations will have already been handled by add_or_paren above.
-            i = i.trim_start();
-            if i.is_empty() || i.as_bytes()[0] != b'*' {
+    fn write_operand(
+        &self,
+        operand: &Expr,
+        parent: BinOp,
+        is_rhs: bool,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        if self.needs_parens(operand, parent, is_rhs) {
+            write!(f, "(")?;
+            self.write(operand, f)?;
+            write!(f, ")")
+        } else {
+            self.write(operand, f)
+        }
+    }
+}
+
+impl<'a> fmt::Display for ExprDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(self.expr, f)
+    }
+}
+
+// A Pratt parser over the token stream.
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    idx: usize,
+    table: &'a OperatorTable,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<(usize, Token), ParseError> {
+        let token = *self.tokens.get(self.idx).ok_or(ParseError::UnexpectedEnd)?;
+        self.idx += 1;
+        Ok(token)
+    }
+
+    // Parse an expression whose operators all have at least min_precedence.
+    // This is wider than a precedence so that it can be one above the
+    // highest.
+    fn expr(&mut self, min_precedence: u16) -> Result<Expr, ParseError> {
+        let mut lhs = self.atom()?;
+        while let Some(&(pos, Token::Op(op))) = self.tokens.get(self.idx) {
+            let (precedence, assoc) = self
+                .table
+                .get(op)
+                .ok_or(ParseError::UnsupportedOperator { pos, op })?;
+            let precedence = u16::from(precedence);
+            if precedence < min_precedence {
                 break;
             }
-            i = &i[1..];
-            // '*' has been seen, now get the rhs of the multiplication. Using
-            // add_or_paren here again will first sum all consecutive
-            // '+' tokens prior to doing the multiplication.
-            if let Some((rem, rhs)) = add_or_paren(i) {
-                lhs *= rhs;
-                i = rem;
-            } else {
-                break;
+            self.idx += 1;
+            let rhs = self.expr(match assoc {
+                Assoc::Left => precedence + 1,
+                Assoc::Right => precedence,
+            })?;
+            lhs = Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    // A number or a parenthesized expression.
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        match self.next()? {
+            (_, Token::Num(n)) => Ok(Expr::Num(n)),
+            (_, Token::LParen) => {
+                let expr = self.expr(0)?;
+                match self.next()? {
+                    (_, Token::RParen) => Ok(expr),
+                    (pos, token) => Err(ParseError::UnexpectedToken { pos, token }),
+                }
             }
+            (pos, token) => Err(ParseError::UnexpectedToken { pos, token }),
         }
-        Some((i, lhs))
+    }
+}
+
+fn sum_lines(input: &str, table: &OperatorTable) -> i64 {
+    input
+        .split('\n')
+        .map(|line| Expr::parse(line, table).unwrap().eval().unwrap())
+        .try_fold(0i64, |sum, value| sum.checked_add(value))
+        .expect("sum overflowed")
+}
+
+#[aoc(day18, part1)]
+fn solve_d18_p1(input: &str) -> i64 {
+    sum_lines(input, &OperatorTable::same_precedence())
+}
+
+#[aoc(day18, part2)]
+fn solve_d18_p2(input: &str) -> i64 {
+    sum_lines(input, &OperatorTable::addition_first())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str, table: &OperatorTable) -> i64 {
+        Expr::parse(input, table).unwrap().eval().unwrap()
     }
 
-    input.split('\n').map(|line| expr(line).unwrap().1).sum()
+    #[test]
+    fn test_examples() {
+        let same = OperatorTable::same_precedence();
+        let addition = OperatorTable::addition_first();
+        assert_eq!(eval("2 * 3 + (4 * 5)", &same), 26);
+        assert_eq!(eval("2 * 3 + (4 * 5)", &addition), 46);
+        let input = "((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2";
+        assert_eq!(eval(input, &same), 13632);
+        assert_eq!(eval(input, &addition), 23340);
+    }
+
+    #[test]
+    fn test_operators() {
+        let same = OperatorTable::same_precedence();
+        assert_eq!(eval("2 ^ 3 ^ 2", &same), 512);
+        assert_eq!(eval("10 - 4 - 3", &same), 3);
+        assert_eq!(eval("1 - 5 * 2", &same), -8);
+        assert_eq!(eval("20 / 3 / 2", &same), 3);
+        let right = OperatorTable::new().with(BinOp::Sub, 1, Assoc::Right);
+        assert_eq!(eval("10 - 4 - 3", &right), 9);
+
+        assert_eq!(
+            Expr::parse("7 / (2 - 2)", &same).unwrap().eval(),
+            Err(EvalError::InvalidOperation {
+                op: BinOp::Div,
+                lhs: 7,
+                rhs: 0
+            })
+        );
+        assert!(Expr::parse("99999999999 * 99999999999", &same)
+            .unwrap()
+            .eval()
+            .is_err());
+        assert!(Expr::parse("2 ^ (0 - 1)", &same).unwrap().eval().is_err());
+
+        // The highest precedence still associates to the left.
+        let highest = OperatorTable::new().with(BinOp::Sub, u8::MAX, Assoc::Left);
+        assert_eq!(Expr::parse("10 - 4 - 3", &highest).unwrap().eval(), Ok(3));
+    }
+
+    #[test]
+    fn test_display() {
+        let same = OperatorTable::same_precedence();
+        let addition = OperatorTable::addition_first();
+        let input = "2 * 3 + (4 * 5)";
+        let parsed = |table| Expr::parse(input, table).unwrap();
+        assert_eq!(parsed(&same).to_string(), "(2 * 3) + (4 * 5)");
+        assert_eq!(parsed(&addition).to_string(), "2 * (3 + (4 * 5))");
+        assert_eq!(parsed(&addition).display_with(&addition).to_string(), input);
+        assert_eq!(
+            parsed(&addition).display_with(&same).to_string(),
+            "2 * (3 + (4 * 5))"
+        );
+
+        let pow = Expr::parse("2 ^ 3 ^ 2", &same).unwrap();
+        assert_eq!(pow.to_string(), "2 ^ (3 ^ 2)");
+        assert_eq!(pow.display_with(&same).to_string(), "2 ^ 3 ^ 2");
+        let sub = Expr::parse("10 - (4 - 3) - 1", &same).unwrap();
+        assert_eq!(sub.display_with(&same).to_string(), "10 - (4 - 3) - 1");
+    }
+
+    #[test]
+    fn test_parenthesized() {
+        let same = OperatorTable::same_precedence();
+        let addition = OperatorTable::addition_first();
+        let input = "1 + 2 * 3 + 4 * 5 + 6";
+        assert_eq!(
+            Expr::parenthesized(input, &same).unwrap(),
+            "((((1 + 2) * 3) + 4) * 5) + 6"
+        );
+        assert_eq!(
+            Expr::parenthesized(input, &addition).unwrap(),
+            "((1 + 2) * (3 + 4)) * (5 + 6)"
+        );
+        assert_eq!(Expr::parenthesized("7", &same).unwrap(), "7");
+        assert_eq!(
+            Expr::parenthesized("1 +", &same),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let same = OperatorTable::same_precedence();
+        let addition = OperatorTable::addition_first();
+        let input = "2 * 3 + (4 * 5)";
+        assert_eq!(
+            Expr::parse(input, &same).unwrap().trace(&same),
+            Ok(vec![
+                input.to_string(),
+                "6 + (4 * 5)".to_string(),
+                "6 + 20".to_string(),
+                "26".to_string()
+            ])
+        );
+        assert_eq!(
+            Expr::parse(input, &addition).unwrap().trace(&addition),
+            Ok(vec![
+                input.to_string(),
+                "2 * 3 + 20".to_string(),
+                "2 * 23".to_string(),
+                "46".to_string()
+            ])
+        );
+        assert!(Expr::parse("1 + 2 / 0", &same)
+            .unwrap()
+            .trace(&same)
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let same = OperatorTable::same_precedence();
+        assert_eq!(Expr::parse("1 +", &same), Err(ParseError::UnexpectedEnd));
+        assert_eq!(
+            Expr::parse("1 $ 2", &same),
+            Err(ParseError::UnexpectedChar { pos: 2, ch: '$' })
+        );
+        assert_eq!(
+            Expr::parse("(1 2", &same),
+            Err(ParseError::UnexpectedToken {
+                pos: 3,
+                token: Token::Num(2)
+            })
+        );
+        assert_eq!(
+            Expr::parse("1 )", &same),
+            Err(ParseError::UnexpectedToken {
+                pos: 2,
+                token: Token::RParen
+            })
+        );
+        assert_eq!(
+            Expr::parse("1 - 2", &OperatorTable::new()),
+            Err(ParseError::UnsupportedOperator {
+                pos: 2,
+                op: BinOp::Sub
+            })
+        );
+        assert_eq!(
+            Expr::parse("99999999999999999999", &same),
+            Err(ParseError::NumberTooLarge { pos: 0 })
+        );
+    }
 }
@@ -1,107 +1,898 @@
 use crate::split_once;
 use aoc_runner_derive::aoc;
-use std::collections::HashMap;
-
-#[derive(Debug)]
-enum Parser<'a> {
-    Lit(&'a str),
-    Seq(Vec<usize>),
-    Alt(Vec<usize>, Vec<usize>),
-}
-
-impl<'a> Parser<'a> {
-    fn new(parser_def: &'a str) -> Option<(usize, Self)> {
-        let (idx, def) = split_once(parser_def, ": ")?;
-        let idx = idx.parse().ok()?;
-        if let Some((a, b)) = split_once(def, " | ") {
-            let a = a
-                .split(' ')
-                .map(|x| x.parse().ok())
-                .collect::<Option<Vec<usize>>>()?;
-            let b = b
-                .split(' ')
-                .map(|x| x.parse().ok())
-                .collect::<Option<Vec<usize>>>()?;
-            Some((idx, Parser::Alt(a, b)))
-        } else if let Some(_) = def.find('"') {
-            Some((idx, Parser::Lit(&def[1..def.len() - 1])))
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+
+/// A term in a rule alternative, as written in the rule definitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    Lit(String),
+    Rule(usize),
+}
+
+type Alternatives = Vec<Vec<Term>>;
+
+/// Message rule definitions, keyed by rule id. Each rule has any number of
+/// alternatives, and each alternative is a sequence of terms.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rules {
+    defs: BTreeMap<usize, Alternatives>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarError {
+    /// The definition on `line` (counted from 1) couldn't be parsed.
+    InvalidRule {
+        line: usize,
+    },
+    DuplicateRule {
+        id: usize,
+    },
+    UndefinedRule {
+        id: usize,
+    },
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GrammarError::InvalidRule { line } => write!(f, "invalid rule on line {}", line),
+            GrammarError::DuplicateRule { id } => write!(f, "rule {} is defined twice", id),
+            GrammarError::UndefinedRule { id } => write!(f, "rule {} is not defined", id),
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+impl Rules {
+    /// Parse one definition per line, such as `0: 4 1 5`, `1: 2 3 | 3 2` or
+    /// `4: "a"`. Literals can't contain quotes.
+    pub fn parse(input: &str) -> Result<Self, GrammarError> {
+        let mut rules = Rules::default();
+        for (id, alts) in parse_defs(input)? {
+            if rules.defs.insert(id, alts).is_some() {
+                return Err(GrammarError::DuplicateRule { id });
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Parse definitions like `parse`, replacing any existing rules with the
+    /// same ids.
+    pub fn replace(&mut self, input: &str) -> Result<(), GrammarError> {
+        self.defs.extend(parse_defs(input)?);
+        Ok(())
+    }
+
+    pub fn get(&self, id: usize) -> Option<&[Vec<Term>]> {
+        self.defs.get(&id).map(|alts| alts.as_slice())
+    }
+
+    /// Resolve every rule reference, failing if any rule is undefined.
+    pub fn compile(&self) -> Result<Grammar, GrammarError> {
+        let index: HashMap<usize, usize> = self
+            .defs
+            .keys()
+            .enumerate()
+            .map(|(idx, &id)| (id, idx))
+            .collect();
+        let mut rules = Vec::with_capacity(self.defs.len());
+        for (&id, alts) in &self.defs {
+            let mut compiled = Vec::with_capacity(alts.len());
+            for alt in alts {
+                let symbols = alt
+                    .iter()
+                    .map(|term| match term {
+                        Term::Lit(s) => Ok(Symbol::Lit(s.as_bytes().into())),
+                        Term::Rule(id) => index
+                            .get(id)
+                            .map(|&idx| Symbol::Rule(idx))
+                            .ok_or(GrammarError::UndefinedRule { id: *id }),
+                    })
+                    .collect::<Result<_, _>>()?;
+                compiled.push(symbols);
+            }
+            rules.push(CompiledRule { id, alts: compiled });
+        }
+        let nullable = nullable_rules(&rules);
+        let mut num_slots = 0;
+        let slots = rules
+            .iter()
+            .map(|rule| {
+                rule.alts
+                    .iter()
+                    .map(|alt| {
+                        let base = num_slots;
+                        num_slots += alt.len() + 1;
+                        base
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Grammar {
+            rules,
+            index,
+            nullable,
+            slots,
+            num_slots,
+        })
+    }
+}
+
+fn parse_defs(input: &str) -> Result<Vec<(usize, Alternatives)>, GrammarError> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| parse_def(line).ok_or(GrammarError::InvalidRule { line: idx + 1 }))
+        .collect()
+}
+
+// Literals run from one quote to the next, so they may contain spaces and
+// `|` but not quotes.
+fn parse_def(line: &str) -> Option<(usize, Alternatives)> {
+    let (id, def) = split_once(line.trim(), ":")?;
+    let id = id.trim().parse().ok()?;
+    let mut alts = vec![Vec::new()];
+    let mut rest = def.trim_start();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            alts.last_mut()?.push(Term::Lit(quoted[..end].to_string()));
+            rest = &quoted[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('|') {
+            alts.push(Vec::new());
+            rest = after;
         } else {
-            let seq = def
-                .split(' ')
-                .map(|x| x.parse().ok())
-                .collect::<Option<Vec<usize>>>()?;
-            Some((idx, Parser::Seq(seq)))
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '|' || c == '"')
+                .unwrap_or(rest.len());
+            alts.last_mut()?.push(Term::Rule(rest[..end].parse().ok()?));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    if alts.iter().any(Vec::is_empty) {
+        return None;
+    }
+    Some((id, alts))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Symbol {
+    Lit(Box<[u8]>),
+    // An index into Grammar::rules.
+    Rule(usize),
+}
+
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    id: usize,
+    alts: Vec<Box<[Symbol]>>,
+}
+
+/// Rules with references resolved, matched with an Earley parser so any
+/// kind of recursion is allowed.
+#[derive(Debug, Clone)]
+pub struct Grammar {
+    rules: Vec<CompiledRule>,
+    // Rule id to index in rules.
+    index: HashMap<usize, usize>,
+    // Whether each rule can match the empty string.
+    nullable: Vec<bool>,
+    // Each (rule, alternative, dot) gets a slot number, starting from
+    // slots[rule][alt] for dot 0.
+    slots: Vec<Vec<usize>>,
+    num_slots: usize,
+}
+
+// Rules that can match the empty string, found by iterating to a fixed
+// point.
+fn nullable_rules(rules: &[CompiledRule]) -> Vec<bool> {
+    let mut nullable = vec![false; rules.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (idx, rule) in rules.iter().enumerate() {
+            if nullable[idx] {
+                continue;
+            }
+            let is_nullable = rule.alts.iter().any(|alt| {
+                alt.iter().all(|symbol| match symbol {
+                    Symbol::Lit(s) => s.is_empty(),
+                    Symbol::Rule(rule) => nullable[*rule],
+                })
+            });
+            if is_nullable {
+                nullable[idx] = true;
+                changed = true;
+            }
+        }
+    }
+    nullable
+}
+
+// An Earley item: the rule alternative being matched, how many of its
+// symbols have matched, and the position the match started at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Item {
+    rule: usize,
+    alt: usize,
+    dot: usize,
+    origin: usize,
+}
+
+impl Item {
+    fn advance(self) -> Item {
+        Item {
+            dot: self.dot + 1,
+            ..self
+        }
+    }
+}
+
+// The Earley item sets for every position in a message. The buffers are
+// kept between messages.
+struct Chart {
+    sets: Vec<Vec<Item>>,
+    // The length of the message the sets are for.
+    len: usize,
+    // Items scanned into the position being processed, before removing
+    // duplicates.
+    pending: Vec<Item>,
+    // The origins of the items at the position being processed, by slot,
+    // and the slots that have any.
+    origins: Vec<Vec<usize>>,
+    touched: Vec<usize>,
+}
+
+impl Chart {
+    fn new(grammar: &Grammar) -> Self {
+        Chart {
+            sets: Vec::new(),
+            len: 0,
+            pending: Vec::new(),
+            origins: vec![Vec::new(); grammar.num_slots],
+            touched: Vec::new(),
+        }
+    }
+}
+
+/// Matches messages against a grammar, reusing its buffers between
+/// messages.
+pub struct Matcher<'a> {
+    grammar: &'a Grammar,
+    chart: Chart,
+}
+
+impl<'a> Matcher<'a> {
+    /// The same as `Grammar::matches`.
+    pub fn matches(&mut self, rule: usize, message: &str) -> bool {
+        let rule = match self.grammar.index.get(&rule) {
+            Some(&rule) => rule,
+            None => return false,
+        };
+        self.grammar
+            .fill_chart(&mut self.chart, rule, message.as_bytes());
+        self.grammar.is_match(&self.chart, rule, message.len())
+    }
+}
+
+impl Grammar {
+    /// Whether the whole message matches the rule with the given id. Rules
+    /// that aren't defined match nothing.
+    pub fn matches(&self, rule: usize, message: &str) -> bool {
+        self.matcher().matches(rule, message)
+    }
+
+    /// A matcher for checking many messages.
+    pub fn matcher(&self) -> Matcher<'_> {
+        Matcher {
+            grammar: self,
+            chart: Chart::new(self),
+        }
+    }
+
+    /// The parse trees for the ways the whole message matches the rule with
+    /// the given id, up to `limit` of them. Derivations that go around a
+    /// cycle of rules without consuming any input are skipped. If the
+    /// message doesn't match, the error says how far it got.
+    pub fn parse(
+        &self,
+        rule: usize,
+        message: &str,
+        limit: usize,
+    ) -> Result<Vec<ParseTree>, MatchError> {
+        let rule = *self
+            .index
+            .get(&rule)
+            .ok_or(MatchError::UndefinedRule { id: rule })?;
+        let input = message.as_bytes();
+        let mut chart = Chart::new(self);
+        self.fill_chart(&mut chart, rule, input);
+        if !self.is_match(&chart, rule, input.len()) {
+            return Err(self.failure(&chart, rule));
+        }
+        let seen = (0..=input.len())
+            .flat_map(|pos| chart.sets[pos].iter().map(move |&item| (pos, item)))
+            .collect();
+        let mut builder = TreeBuilder {
+            grammar: self,
+            seen,
+            input,
+            limit,
+            path: Vec::new(),
+            cuts: 0,
+            tree_memo: HashMap::new(),
+            children_memo: HashMap::new(),
+        };
+        Ok(builder.trees(rule, 0, input.len()))
+    }
+
+    fn is_match(&self, chart: &Chart, rule: usize, end: usize) -> bool {
+        chart.sets[end]
+            .iter()
+            .any(|item| item.rule == rule && item.origin == 0 && self.is_complete(item))
+    }
+
+    // What was expected at the furthest position the chart reached.
+    fn failure(&self, chart: &Chart, rule: usize) -> MatchError {
+        let position = (0..=chart.len)
+            .rev()
+            .find(|&pos| !chart.sets[pos].is_empty())
+            .unwrap_or(0);
+        let mut expected = Vec::new();
+        if position < chart.len && self.is_match(chart, rule, position) {
+            expected.push(Expected::End);
+        }
+        for item in &chart.sets[position] {
+            match self.symbols(item).get(item.dot) {
+                Some(Symbol::Lit(s)) if !s.is_empty() => {
+                    expected.push(Expected::Lit(String::from_utf8_lossy(s).into_owned()))
+                }
+                Some(&Symbol::Rule(rule)) => expected.push(Expected::Rule(self.rules[rule].id)),
+                _ => {}
+            }
+        }
+        expected.sort();
+        expected.dedup();
+        MatchError::Unexpected { position, expected }
+    }
+
+    fn symbols(&self, item: &Item) -> &[Symbol] {
+        &self.rules[item.rule].alts[item.alt]
+    }
+
+    fn is_complete(&self, item: &Item) -> bool {
+        item.dot == self.symbols(item).len()
+    }
+
+    // Add an item to the position being processed unless it's already
+    // there.
+    fn add_item(&self, chart: &mut Chart, pos: usize, item: Item) {
+        let slot = self.slots[item.rule][item.alt] + item.dot;
+        let origins = &mut chart.origins[slot];
+        if origins.contains(&item.origin) {
+            return;
+        }
+        if origins.is_empty() {
+            chart.touched.push(slot);
         }
+        origins.push(item.origin);
+        chart.sets[pos].push(item);
     }
 
-    // returns Some(remaining_input) when valid, None when doesn't match.
-    fn parse<'b>(&self, parsers: &HashMap<usize, Parser>, input: &'b str) -> Vec<&'b str> {
+    fn fill_chart(&self, chart: &mut Chart, start: usize, input: &[u8]) {
+        chart.len = input.len();
+        if chart.sets.len() <= input.len() {
+            chart.sets.resize_with(input.len() + 1, Vec::new);
+        }
+        for set in &mut chart.sets[..=input.len()] {
+            set.clear();
+        }
+        for alt in 0..self.rules[start].alts.len() {
+            chart.sets[0].push(Item {
+                rule: start,
+                alt,
+                dot: 0,
+                origin: 0,
+            });
+        }
+        for pos in 0..=input.len() {
+            // Items scanned into this position may repeat, so re-add them.
+            std::mem::swap(&mut chart.sets[pos], &mut chart.pending);
+            for idx in 0..chart.pending.len() {
+                let item = chart.pending[idx];
+                self.add_item(chart, pos, item);
+            }
+            chart.pending.clear();
+
+            let mut next = 0;
+            while let Some(&item) = chart.sets[pos].get(next) {
+                next += 1;
+                match self.symbols(&item).get(item.dot) {
+                    Some(Symbol::Lit(s)) if s.is_empty() => {
+                        self.add_item(chart, pos, item.advance());
+                    }
+                    Some(Symbol::Lit(s)) => {
+                        if input[pos..].starts_with(s) {
+                            chart.sets[pos + s.len()].push(item.advance());
+                        }
+                    }
+                    Some(&Symbol::Rule(rule)) => {
+                        for alt in 0..self.rules[rule].alts.len() {
+                            let predicted = Item {
+                                rule,
+                                alt,
+                                dot: 0,
+                                origin: pos,
+                            };
+                            self.add_item(chart, pos, predicted);
+                        }
+                        // A nullable rule may already have been completed
+                        // at this position, so skip over it right away.
+                        if self.nullable[rule] {
+                            self.add_item(chart, pos, item.advance());
+                        }
+                    }
+                    None => {
+                        for idx in 0.. {
+                            let waiting = match chart.sets[item.origin].get(idx) {
+                                Some(&waiting) => waiting,
+                                None => break,
+                            };
+                            if self.symbols(&waiting).get(waiting.dot)
+                                == Some(&Symbol::Rule(item.rule))
+                            {
+                                self.add_item(chart, pos, waiting.advance());
+                            }
+                        }
+                    }
+                }
+            }
+            for slot in chart.touched.drain(..) {
+                chart.origins[slot].clear();
+            }
+        }
+    }
+}
+
+/// How a rule matched part of a message. Spans are byte ranges of the
+/// message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseTree {
+    Lit {
+        text: String,
+        span: Range<usize>,
+    },
+    Rule {
+        id: usize,
+        /// The index of the alternative that matched.
+        alt: usize,
+        span: Range<usize>,
+        children: Vec<ParseTree>,
+    },
+}
+
+impl ParseTree {
+    pub fn span(&self) -> Range<usize> {
         match self {
-            &Parser::Lit(s) => {
-                if input.is_empty() || &input[..s.len()] != s {
-                    Vec::new()
-                } else {
-                    vec![&input[s.len()..]]
+            ParseTree::Lit { span, .. } | ParseTree::Rule { span, .. } => span.clone(),
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{:1$}", "", depth * 2)?;
+        match self {
+            ParseTree::Lit { text, span } => writeln!(f, "{:?} {:?}", text, span),
+            ParseTree::Rule {
+                id,
+                alt,
+                span,
+                children,
+            } => {
+                writeln!(f, "{} (alt {}) {:?}", id, alt, span)?;
+                children
+                    .iter()
+                    .try_for_each(|child| child.write(f, depth + 1))
+            }
+        }
+    }
+}
+
+/// One line per node, indented by depth.
+impl fmt::Display for ParseTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Expected {
+    Lit(String),
+    Rule(usize),
+    /// The message could have ended here.
+    End,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expected::Lit(s) => write!(f, "{:?}", s),
+            Expected::Rule(id) => write!(f, "rule {}", id),
+            Expected::End => write!(f, "end of message"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchError {
+    UndefinedRule {
+        id: usize,
+    },
+    /// No match could be continued past `position`, where one of `expected`
+    /// was needed.
+    Unexpected {
+        position: usize,
+        expected: Vec<Expected>,
+    },
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchError::UndefinedRule { id } => write!(f, "rule {} is not defined", id),
+            MatchError::Unexpected { position, expected } => {
+                write!(f, "no match past position {}", position)?;
+                for (idx, expected) in expected.iter().enumerate() {
+                    let sep = if idx == 0 { "; expected" } else { "," };
+                    write!(f, "{} {}", sep, expected)?;
                 }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MatchError {}
+
+// Reads parse trees back out of a finished chart.
+struct TreeBuilder<'a> {
+    grammar: &'a Grammar,
+    // Every item in the chart with its position.
+    seen: HashSet<(usize, Item)>,
+    input: &'a [u8],
+    limit: usize,
+    // The (rule, start, end) spans being built, to skip cyclic derivations,
+    // and how many times one was skipped.
+    path: Vec<(usize, usize, usize)>,
+    cuts: usize,
+    // Results for each span and each item ending at a position. Results
+    // that skipped a cycle depend on the path, so they aren't kept.
+    tree_memo: HashMap<(usize, usize, usize), Vec<ParseTree>>,
+    children_memo: HashMap<(Item, usize), Vec<Vec<ParseTree>>>,
+}
+
+impl<'a> TreeBuilder<'a> {
+    fn trees(&mut self, rule: usize, start: usize, end: usize) -> Vec<ParseTree> {
+        let key = (rule, start, end);
+        if let Some(trees) = self.tree_memo.get(&key) {
+            return trees.clone();
+        }
+        if self.path.contains(&key) {
+            self.cuts += 1;
+            return Vec::new();
+        }
+        let cuts = self.cuts;
+        self.path.push(key);
+        let mut trees = Vec::new();
+        let compiled = &self.grammar.rules[rule];
+        for (alt, symbols) in compiled.alts.iter().enumerate() {
+            let item = Item {
+                rule,
+                alt,
+                dot: symbols.len(),
+                origin: start,
+            };
+            if !self.seen.contains(&(end, item)) {
+                continue;
             }
-            Parser::Seq(a) => {
-                let parser_idx = a[0];
-                let p = parsers.get(&parser_idx).unwrap();
-                let remaining = p.parse(parsers, input);
-
-                let subsequent_parsers = &a[1..];
-                if subsequent_parsers.is_empty() {
-                    return remaining;
+            for children in self.children(item, end) {
+                if trees.len() == self.limit {
+                    break;
                 }
+                trees.push(ParseTree::Rule {
+                    id: compiled.id,
+                    alt,
+                    span: start..end,
+                    children,
+                });
+            }
+        }
+        self.path.pop();
+        if self.cuts == cuts {
+            self.tree_memo.insert(key, trees.clone());
+        }
+        trees
+    }
 
-                let mut subsequent_remaining = Vec::new();
-                let subsequent_parser = Parser::Seq(subsequent_parsers.to_vec());
-                for input in remaining {
-                    subsequent_remaining.extend(subsequent_parser.parse(parsers, input));
+    // The ways the symbols before the item's dot can match the input from
+    // the item's origin to end.
+    fn children(&mut self, item: Item, end: usize) -> Vec<Vec<ParseTree>> {
+        if let Some(children) = self.children_memo.get(&(item, end)) {
+            return children.clone();
+        }
+        let cuts = self.cuts;
+        let children = self.find_children(item, end);
+        if self.cuts == cuts {
+            self.children_memo.insert((item, end), children.clone());
+        }
+        children
+    }
+
+    fn find_children(&mut self, item: Item, end: usize) -> Vec<Vec<ParseTree>> {
+        if item.dot == 0 {
+            return if item.origin == end {
+                vec![Vec::new()]
+            } else {
+                Vec::new()
+            };
+        }
+        let prev = Item {
+            dot: item.dot - 1,
+            ..item
+        };
+        let grammar = self.grammar;
+        let mut result = Vec::new();
+        match &grammar.symbols(&item)[prev.dot] {
+            Symbol::Lit(s) => {
+                if self.input[..end].ends_with(s) && self.seen.contains(&(end - s.len(), prev)) {
+                    let mid = end - s.len();
+                    for mut children in self.children(prev, mid) {
+                        children.push(ParseTree::Lit {
+                            text: String::from_utf8_lossy(s).into_owned(),
+                            span: mid..end,
+                        });
+                        result.push(children);
+                    }
                 }
-                subsequent_remaining
             }
-            Parser::Alt(a, b) => {
-                let parser_a = Parser::Seq(a.clone());
-                let parser_b = Parser::Seq(b.clone());
-                let mut remaining = parser_a.parse(parsers, input);
-                remaining.extend(parser_b.parse(parsers, input));
-                remaining
+            &Symbol::Rule(rule) => {
+                for mid in item.origin..=end {
+                    if !self.seen.contains(&(mid, prev)) {
+                        continue;
+                    }
+                    let subtrees = self.trees(rule, mid, end);
+                    if subtrees.is_empty() {
+                        continue;
+                    }
+                    for prefix in self.children(prev, mid) {
+                        for subtree in &subtrees {
+                            if result.len() == self.limit {
+                                return result;
+                            }
+                            let mut children = prefix.clone();
+                            children.push(subtree.clone());
+                            result.push(children);
+                        }
+                    }
+                }
             }
         }
+        result.truncate(self.limit);
+        result
     }
 }
 
+fn parse_input(input: &str) -> (Rules, &str) {
+    let (rules, messages) = split_once(input, "\n\n").unwrap();
+    (Rules::parse(rules).unwrap(), messages)
+}
+
+fn count_matches(rules: &Rules, messages: &str) -> usize {
+    let grammar = rules.compile().unwrap();
+    let mut matcher = grammar.matcher();
+    messages
+        .lines()
+        .filter(|message| matcher.matches(0, message))
+        .count()
+}
+
 #[aoc(day19, part1)]
 fn solve_d19_p1(input: &str) -> usize {
-    let (parser_input, pattern_input) = split_once(input, "\n\n").unwrap();
-    let parsers: HashMap<usize, Parser> = parser_input
-        .split('\n')
-        .map(|parser_def| Parser::new(parser_def).unwrap())
-        .collect();
-
-    let rule0 = parsers.get(&0).unwrap();
-    pattern_input
-        .split('\n')
-        .filter(|pattern| rule0.parse(&parsers, pattern).iter().any(|x| x.is_empty()))
-        .count()
+    let (rules, messages) = parse_input(input);
+    count_matches(&rules, messages)
 }
 
 #[aoc(day19, part2)]
 fn solve_d19_p2(input: &str) -> usize {
-    let (parser_input, pattern_input) = split_once(input, "\n\n").unwrap();
-    let mut parsers: HashMap<usize, Parser> = parser_input
-        .split('\n')
-        .map(|parser_def| Parser::new(parser_def).unwrap())
-        .collect();
+    let (mut rules, messages) = parse_input(input);
+    rules.replace("8: 42 | 42 8\n11: 42 31 | 42 11 31").unwrap();
+    count_matches(&rules, messages)
+}
 
-    parsers.insert(8, Parser::Alt(vec![42], vec![42, 8]));
-    parsers.insert(11, Parser::Alt(vec![42, 31], vec![42, 11, 31]));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let rule0 = parsers.get(&0).unwrap();
+    const EXAMPLE: &str = "42: 9 14 | 10 1
+9: 14 27 | 1 26
+10: 23 14 | 28 1
+1: \"a\"
+11: 42 31
+5: 1 14 | 15 1
+19: 14 1 | 14 14
+12: 24 14 | 19 1
+16: 15 1 | 14 14
+31: 14 17 | 1 13
+6: 14 14 | 1 14
+2: 1 24 | 14 4
+0: 8 11
+13: 14 3 | 1 12
+15: 1 | 14
+17: 14 2 | 1 7
+23: 25 1 | 22 14
+28: 16 1
+4: 1 1
+20: 14 14 | 1 15
+3: 5 14 | 16 1
+27: 1 6 | 14 18
+14: \"b\"
+21: 14 1 | 1 14
+25: 1 1 | 1 14
+22: 14 14
+8: 42
+26: 14 22 | 1 20
+18: 15 15
+7: 14 5 | 1 21
+24: 14 1
 
-    pattern_input
-        .split('\n')
-        .filter(|pattern| rule0.parse(&parsers, pattern).iter().any(|x| x.is_empty()))
-        .count()
+abbbbbabbbaaaababbaabbbbabababbbabbbbbbabaaaa
+bbabbbbaabaabba
+babbbbaabbbbbabbbbbbaabaaabaaa
+aaabbbbbbaaaabaababaabababbabaaabbababababaaa
+bbbbbbbaaaabbbbaaabbabaaa
+bbbababbbbaaaaaaaabbababaaababaabab
+ababaaaaaabaaab
+ababaaaaabbbaba
+baabbaaaabbaaaababbaababb
+abbbbabbbbaaaababbbbbbaaaababb
+aaaaabbaabaaaaababaa
+aaaabbaaaabbaaa
+aaaabbaabbaaaaaaabbbabbbaaabbaabaaa
+babaaabbbaaabaababbaabababaaab
+aabbbbbaabbbaaaaaabbbbbababaaaaabbaaabba";
+
+    #[test]
+    fn test_example() {
+        assert_eq!(solve_d19_p1(EXAMPLE), 3);
+        assert_eq!(solve_d19_p2(EXAMPLE), 12);
+    }
+
+    #[test]
+    fn test_grammar() {
+        let grammar = |defs| Rules::parse(defs).unwrap().compile().unwrap();
+
+        // Literals longer than the rest of the message.
+        let short = grammar("0: \"ab\" 1\n1: \"cd\"");
+        assert!(short.matches(0, "abcd"));
+        assert!(!short.matches(0, "abc"));
+        assert!(!short.matches(0, "a"));
+        assert!(!short.matches(0, ""));
+        assert!(!short.matches(2, "abcd"));
+
+        // Left, right and middle recursion, and an empty literal.
+        let recursive = grammar("0: 0 1 | 1\n1: \"a\"\n2: 1 2 | 3\n3: \"(\" 3 \")\" | \"\"");
+        assert!(recursive.matches(0, "aaaa"));
+        assert!(!recursive.matches(0, ""));
+        assert!(recursive.matches(2, "aa(())"));
+        assert!(recursive.matches(2, ""));
+        assert!(!recursive.matches(2, "a(()"));
+
+        // Literals with spaces and bars, and a matcher reused across
+        // messages of different lengths.
+        let quoted = grammar("0: \"a b\" | \"|\" 0");
+        let mut matcher = quoted.matcher();
+        assert!(matcher.matches(0, "||a b"));
+        assert!(!matcher.matches(0, "a"));
+        assert!(matcher.matches(0, "a b"));
+        assert!(!matcher.matches(0, "ab"));
+
+        // Cycles through nullable rules.
+        let nullable = grammar("0: 1 0 2 | 2\n1: \"\" | 0\n2: \"x\"");
+        assert!(nullable.matches(0, "xxx"));
+        assert!(!nullable.matches(0, "xy"));
+    }
+
+    #[test]
+    fn test_parse_trees() {
+        let grammar = |defs| Rules::parse(defs).unwrap().compile().unwrap();
+
+        let simple = grammar("0: 1 2\n1: \"a\" | \"a\" 1\n2: \"b\"");
+        let trees = simple.parse(0, "aab", 10).unwrap();
+        assert_eq!(trees.len(), 1);
+        assert_eq!(
+            trees[0].to_string(),
+            "0 (alt 0) 0..3
+  1 (alt 1) 0..2
+    \"a\" 0..1
+    1 (alt 0) 1..2
+      \"a\" 1..2
+  2 (alt 0) 2..3
+    \"b\" 2..3
+"
+        );
+        assert_eq!(
+            simple.parse(0, "aac", 10),
+            Err(MatchError::Unexpected {
+                position: 2,
+                expected: vec![
+                    Expected::Lit("a".to_string()),
+                    Expected::Lit("b".to_string()),
+                    Expected::Rule(1),
+                    Expected::Rule(2),
+                ]
+            })
+        );
+        let extra = simple.parse(0, "aabb", 10).unwrap_err();
+        assert_eq!(
+            extra,
+            MatchError::Unexpected {
+                position: 3,
+                expected: vec![Expected::End]
+            }
+        );
+        assert_eq!(
+            extra.to_string(),
+            "no match past position 3; expected end of message"
+        );
+        assert_eq!(
+            simple.parse(5, "a", 10),
+            Err(MatchError::UndefinedRule { id: 5 })
+        );
+
+        // Every way of bracketing a sequence, up to the limit.
+        let ambiguous = grammar("0: 0 0 | \"a\"");
+        assert_eq!(ambiguous.parse(0, "aaa", 10).unwrap().len(), 2);
+        assert_eq!(ambiguous.parse(0, "aaaa", 10).unwrap().len(), 5);
+        assert_eq!(ambiguous.parse(0, "aaaa", 3).unwrap().len(), 3);
+        let long = "a".repeat(48);
+        assert_eq!(ambiguous.parse(0, &long, 1).unwrap().len(), 1);
+        let trees = ambiguous.parse(0, &long, 20).unwrap();
+        assert_eq!(trees.len(), 20);
+        assert!(trees.iter().all(|tree| tree.span() == (0..48)));
+        assert!((1..trees.len()).all(|idx| !trees[..idx].contains(&trees[idx])));
+
+        // Cycles that consume nothing don't repeat forever.
+        let cyclic = grammar("0: 0 | 1\n1: \"a\" | 0");
+        let trees = cyclic.parse(0, "a", 10).unwrap();
+        assert!(!trees.is_empty());
+        assert!(trees.iter().all(|tree| tree.span() == (0..1)));
+    }
+
+    #[test]
+    fn test_rule_errors() {
+        assert_eq!(
+            Rules::parse("0: 1\n\n1: 2 x"),
+            Err(GrammarError::InvalidRule { line: 3 })
+        );
+        assert_eq!(
+            Rules::parse("0: 1 |"),
+            Err(GrammarError::InvalidRule { line: 1 })
+        );
+        assert_eq!(
+            Rules::parse("0: \"a"),
+            Err(GrammarError::InvalidRule { line: 1 })
+        );
+        assert_eq!(
+            Rules::parse("0: \"a\"\n0: \"b\""),
+            Err(GrammarError::DuplicateRule { id: 0 })
+        );
+        assert_eq!(
+            Rules::parse("0: 1 2\n1: \"a\"").unwrap().compile().err(),
+            Some(GrammarError::UndefinedRule { id: 2 })
+        );
+    }
 }
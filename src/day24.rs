@@ -1,3 +1,4 @@
+use crate::automaton::{Automaton, HexGrid, Rules};
 use aoc_runner_derive::aoc;
 use std::collections::HashMap;
 
@@ -14,6 +15,11 @@ impl Pos {
             Direction::NW => Pos(self.0 - 1, self.1 + 1),
         }
     }
+
+    // Convert from doubled to axial hex coordinates.
+    fn to_axial(self) -> (isize, isize) {
+        ((self.0 - self.1) / 2, self.1)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -97,75 +103,13 @@ fn solve_d24_p2(input: &str) -> usize {
         floor.entry(pos).or_insert(Tile::White).flip()
     }
 
-    for _ in 0..100 {
-        let mut tiles_visited = HashMap::new();
-        for &pos in floor.keys() {
-            run(&floor, &mut tiles_visited, pos);
-        }
-        for pos in tiles_visited
-            .into_iter()
-            .filter_map(|(k, should_flip)| if should_flip { Some(k) } else { None })
-        {
-            floor.entry(pos).or_insert(Tile::White).flip();
-        }
-    }
-
-    floor.values().filter(|&&tile| tile == Tile::Black).count()
-}
-
-fn run(floor: &HashMap<Pos, Tile>, tiles_visited: &mut HashMap<Pos, bool>, pos: Pos) {
-    if tiles_visited.contains_key(&pos) {
-        return;
-    }
-    let n = neighbors(floor, pos);
-    let black_tiles = n
-        .iter()
-        .copied()
-        .filter(|&tile| tile == Tile::Black)
-        .count();
-    let tile = floor.get(&pos).copied().unwrap_or(Tile::White);
-    if (tile == Tile::Black && (black_tiles == 0 || black_tiles > 2))
-        || (tile == Tile::White && black_tiles == 2)
-    {
-        tiles_visited.insert(pos, true);
-    } else {
-        tiles_visited.insert(pos, false);
-    }
-    if black_tiles > 0 {
-        run(floor, tiles_visited, pos.step(Direction::E));
-        run(floor, tiles_visited, pos.step(Direction::SE));
-        run(floor, tiles_visited, pos.step(Direction::SW));
-        run(floor, tiles_visited, pos.step(Direction::W));
-        run(floor, tiles_visited, pos.step(Direction::NE));
-        run(floor, tiles_visited, pos.step(Direction::NW));
-    }
-}
-
-fn neighbors(floor: &HashMap<Pos, Tile>, pos: Pos) -> [Tile; 6] {
-    [
-        floor
-            .get(&pos.step(Direction::E))
-            .copied()
-            .unwrap_or(Tile::White),
-        floor
-            .get(&pos.step(Direction::SE))
-            .copied()
-            .unwrap_or(Tile::White),
-        floor
-            .get(&pos.step(Direction::SW))
-            .copied()
-            .unwrap_or(Tile::White),
-        floor
-            .get(&pos.step(Direction::W))
-            .copied()
-            .unwrap_or(Tile::White),
-        floor
-            .get(&pos.step(Direction::NE))
-            .copied()
-            .unwrap_or(Tile::White),
-        floor
-            .get(&pos.step(Direction::NW))
-            .copied()
-            .unwrap_or(Tile::White),
-    ]
+    // A black tile with zero or more than 2 black neighbors flips to white. A
+    // white tile with exactly 2 black neighbors flips to black.
+    let black_tiles = floor
+        .into_iter()
+        .filter(|&(_, tile)| tile == Tile::Black)
+        .map(|(pos, _)| pos.to_axial());
+    let mut floor = Automaton::new(HexGrid, Rules::new([2], [1, 2]), black_tiles);
+    floor.step_n(100);
+    floor.population()
 }
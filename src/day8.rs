@@ -1,14 +1,17 @@
 use aoc_runner_derive::aoc;
 
-#[derive(Debug, Copy, Clone)]
-enum Instr {
+use std::fmt::{self, Write};
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Instr {
     Nop(isize),
     Acc(isize),
     Jmp(isize),
 }
 
 impl Instr {
-    fn parse(input: &str) -> Option<Instr> {
+    pub fn parse(input: &str) -> Option<Instr> {
         let space_idx = input.find(' ')?;
         let (op, arg) = (&input[..space_idx], &input[space_idx + 1..]);
         let arg = arg.parse().ok()?;
@@ -19,23 +22,69 @@ impl Instr {
             _ => return None,
         })
     }
+
+    /// The instruction with `nop` and `jmp` swapped, or `None` for `acc`.
+    fn flipped(self) -> Option<Instr> {
+        match self {
+            Instr::Nop(arg) => Some(Instr::Jmp(arg)),
+            Instr::Jmp(arg) => Some(Instr::Nop(arg)),
+            Instr::Acc(_) => None,
+        }
+    }
+
+    /// The pc executed after this instruction, or `None` if it would jump
+    /// before the start of the program.
+    fn next_pc(self, pc: usize) -> Option<usize> {
+        match self {
+            Instr::Nop(_) | Instr::Acc(_) => Some(pc + 1),
+            Instr::Jmp(arg) => {
+                let target = pc as isize + arg;
+                if target < 0 {
+                    None
+                } else {
+                    Some(target as usize)
+                }
+            }
+        }
+    }
+
+    fn accum_delta(self) -> isize {
+        match self {
+            Instr::Acc(arg) => arg,
+            Instr::Nop(_) | Instr::Jmp(_) => 0,
+        }
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Nop(arg) => write!(f, "nop {:+}", arg),
+            Instr::Acc(arg) => write!(f, "acc {:+}", arg),
+            Instr::Jmp(arg) => write!(f, "jmp {:+}", arg),
+        }
+    }
+}
+
+pub fn parse_program(input: &str) -> Option<Vec<Instr>> {
+    input.split('\n').map(Instr::parse).collect()
 }
 
 #[derive(Debug, Clone)]
-struct BitSet(Vec<u64>);
+pub struct BitSet(Vec<u64>);
 
 impl BitSet {
-    fn new(max_bits: usize) -> Self {
+    pub fn new(max_bits: usize) -> Self {
         BitSet(vec![0; (max_bits - 1) / 64 + 1])
     }
 
-    fn contains(&self, bit_idx: usize) -> bool {
+    pub fn contains(&self, bit_idx: usize) -> bool {
         let byte_idx = bit_idx / 64;
         let bit_offset = bit_idx & 63;
         self.0[byte_idx] & (1 << bit_offset) != 0
     }
 
-    fn insert(&mut self, bit_idx: usize) {
+    pub fn insert(&mut self, bit_idx: usize) {
         let byte_idx = bit_idx / 64;
         let bit_offset = bit_idx & 63;
         self.0[byte_idx] |= 1 << bit_offset;
@@ -72,52 +121,286 @@ fn step(instr: Instr, prev_instrs: &mut BitSet, mut pc: usize, mut accum: isize)
     (pc, accum)
 }
 
-#[aoc(day8, part1)]
-pub fn solve_d8_p1(input: &str) -> isize {
-    let program = input
-        .split('\n')
-        .map(|line| Instr::parse(line))
-        .collect::<Option<Vec<_>>>()
-        .unwrap();
-    run_program(&program, BitSet::new(program.len()), 0, 0).1
+/// A single instruction patch that makes the program terminate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Repair {
+    /// The position of the patched instruction.
+    pub pc: usize,
+    /// The instruction that replaces it.
+    pub patched: Instr,
+    /// The accumulator when the patched program terminates.
+    pub accum: isize,
 }
 
-#[aoc(day8, part2)]
-pub fn solve_d8_p2(input: &str) -> isize {
-    let mut program = input
-        .split('\n')
-        .map(|line| Instr::parse(line))
-        .collect::<Option<Vec<_>>>()
-        .unwrap();
+/// For every pc in `0..=program.len()`, the accumulator delta collected from
+/// that pc until the program terminates, or `None` if execution starting
+/// there never reaches `program.len()`. Computed by walking the control flow
+/// graph backwards from the terminal pc, so it's linear in the program size.
+fn accum_to_termination(program: &[Instr]) -> Vec<Option<isize>> {
+    let mut preds = vec![Vec::new(); program.len() + 1];
+    for (pc, &instr) in program.iter().enumerate() {
+        if let Some(next) = instr.next_pc(pc).filter(|&next| next <= program.len()) {
+            preds[next].push(pc);
+        }
+    }
+    let mut to_end = vec![None; program.len() + 1];
+    to_end[program.len()] = Some(0);
+    let mut queue = vec![program.len()];
+    while let Some(pc) = queue.pop() {
+        let accum = to_end[pc].unwrap();
+        for &pred in &preds[pc] {
+            to_end[pred] = Some(program[pred].accum_delta() + accum);
+            queue.push(pred);
+        }
+    }
+    to_end
+}
+
+/// Every single `nop`/`jmp` swap that makes a looping program terminate,
+/// along with the accumulator value at termination. A program that already
+/// terminates needs no repair and yields no results.
+///
+/// Only instructions on the original execution path can change the outcome.
+/// Swapping the instruction at `pc` fixes the program iff the swapped
+/// successor can reach termination in the unpatched program: execution is
+/// deterministic, so that path can't pass back through `pc` without falling
+/// into the original loop.
+pub fn find_repairs(program: &[Instr]) -> Vec<Repair> {
+    let to_end = accum_to_termination(program);
+    if to_end[0].is_some() {
+        return Vec::new();
+    }
+    let mut repairs = Vec::new();
+    let mut visited = BitSet::new(program.len());
     let mut pc = 0;
     let mut accum = 0;
-    let mut prev_instrs = BitSet::new(program.len());
-    loop {
-        match program[pc] {
-            Instr::Nop(arg) => {
-                program[pc] = Instr::Jmp(arg);
-                let (new_pc, new_accum) = run_program(&program, prev_instrs.clone(), pc, accum);
-                if new_pc == program.len() {
-                    return new_accum;
+    while pc < program.len() && !visited.contains(pc) {
+        let instr = program[pc];
+        if let Some(patched) = instr.flipped() {
+            let patched_accum = patched
+                .next_pc(pc)
+                .and_then(|next| to_end.get(next).copied().flatten());
+            if let Some(rest) = patched_accum {
+                repairs.push(Repair {
+                    pc,
+                    patched,
+                    accum: accum + rest,
+                });
+            }
+        }
+        let (new_pc, new_accum) = step(instr, &mut visited, pc, accum);
+        pc = new_pc;
+        accum = new_accum;
+    }
+    repairs
+}
+
+/// Where control goes after the last instruction of a basic block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockExit {
+    /// Continues into the block with this index.
+    Block(usize),
+    /// Runs off the end of the program, terminating normally.
+    Terminate,
+    /// Jumps to a pc outside of `0..=program.len()`.
+    OutOfBounds(isize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub instrs: Range<usize>,
+    pub exit: BlockExit,
+}
+
+/// The control flow graph of a console program. Every instruction has exactly
+/// one successor, so each basic block has a single exit and the graph is a
+/// collection of chains feeding into loops or out of the program.
+#[derive(Debug)]
+pub struct ControlFlowGraph<'a> {
+    program: &'a [Instr],
+    blocks: Vec<BasicBlock>,
+}
+
+impl<'a> ControlFlowGraph<'a> {
+    pub fn new(program: &'a [Instr]) -> Self {
+        let mut leaders = BitSet::new(program.len() + 1);
+        leaders.insert(0);
+        for (pc, &instr) in program.iter().enumerate() {
+            if let Instr::Jmp(arg) = instr {
+                leaders.insert(pc + 1);
+                let target = pc as isize + arg;
+                if target >= 0 && (target as usize) < program.len() {
+                    leaders.insert(target as usize);
                 }
-                program[pc] = Instr::Nop(arg);
             }
-            Instr::Jmp(arg) => {
-                program[pc] = Instr::Nop(arg);
-                let (new_pc, new_accum) = run_program(&program, prev_instrs.clone(), pc, accum);
-                if new_pc == program.len() {
-                    return new_accum;
+        }
+        let starts: Vec<usize> = (0..program.len())
+            .filter(|&pc| leaders.contains(pc))
+            .collect();
+        let mut block_of = vec![0; program.len()];
+        for (block_idx, &start) in starts.iter().enumerate() {
+            block_of[start] = block_idx;
+        }
+        let exit_to = |target: isize| {
+            if target == program.len() as isize {
+                BlockExit::Terminate
+            } else if target >= 0 && target < program.len() as isize {
+                BlockExit::Block(block_of[target as usize])
+            } else {
+                BlockExit::OutOfBounds(target)
+            }
+        };
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(block_idx, &start)| {
+                let end = starts.get(block_idx + 1).copied().unwrap_or(program.len());
+                let last = end - 1;
+                let exit = match program[last] {
+                    Instr::Jmp(arg) => exit_to(last as isize + arg),
+                    Instr::Nop(_) | Instr::Acc(_) => exit_to(end as isize),
+                };
+                BasicBlock {
+                    instrs: start..end,
+                    exit,
                 }
-                program[pc] = Instr::Jmp(arg);
+            })
+            .collect();
+        ControlFlowGraph { program, blocks }
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// The index of the block containing the instruction at `pc`.
+    pub fn block_containing(&self, pc: usize) -> Option<usize> {
+        if pc >= self.program.len() {
+            return None;
+        }
+        Some(
+            match self.blocks.binary_search_by_key(&pc, |b| b.instrs.start) {
+                Ok(block_idx) => block_idx,
+                Err(insert_idx) => insert_idx - 1,
+            },
+        )
+    }
+
+    /// The set of block indices executed when starting from pc 0.
+    pub fn reachable_blocks(&self) -> BitSet {
+        let mut reachable = BitSet::new(self.blocks.len() + 1);
+        let mut block_idx = 0;
+        while block_idx < self.blocks.len() && !reachable.contains(block_idx) {
+            reachable.insert(block_idx);
+            match self.blocks[block_idx].exit {
+                BlockExit::Block(next) => block_idx = next,
+                BlockExit::Terminate | BlockExit::OutOfBounds(_) => break,
             }
-            _ => {}
         }
-        let (new_pc, new_accum) = step(program[pc], &mut prev_instrs, pc, accum);
-        pc = new_pc;
-        accum = new_accum;
+        reachable
+    }
+
+    /// The pcs of every instruction that can never be executed.
+    pub fn unreachable_instrs(&self) -> Vec<usize> {
+        let reachable = self.reachable_blocks();
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter(|&(block_idx, _)| !reachable.contains(block_idx))
+            .flat_map(|(_, block)| block.instrs.clone())
+            .collect()
+    }
+
+    /// Every cycle in the graph as a list of block indices, starting from the
+    /// first block of the cycle encountered. Cycles are reported whether or
+    /// not they're reachable from pc 0.
+    pub fn loops(&self) -> Vec<Vec<usize>> {
+        const UNVISITED: u8 = 0;
+        const ON_PATH: u8 = 1;
+        const DONE: u8 = 2;
+        let mut state = vec![UNVISITED; self.blocks.len()];
+        let mut loops = Vec::new();
+        for start in 0..self.blocks.len() {
+            let mut path = Vec::new();
+            let mut next = Some(start);
+            while let Some(block_idx) = next.filter(|&b| state[b] == UNVISITED) {
+                state[block_idx] = ON_PATH;
+                path.push(block_idx);
+                next = match self.blocks[block_idx].exit {
+                    BlockExit::Block(next) => Some(next),
+                    BlockExit::Terminate | BlockExit::OutOfBounds(_) => None,
+                };
+            }
+            if let Some(block_idx) = next.filter(|&b| state[b] == ON_PATH) {
+                let loop_start = path.iter().position(|&b| b == block_idx).unwrap();
+                loops.push(path[loop_start..].to_vec());
+            }
+            for block_idx in path {
+                state[block_idx] = DONE;
+            }
+        }
+        loops
+    }
+
+    /// The pc and target of every jump that leaves the program without
+    /// landing exactly on the terminating pc.
+    pub fn out_of_bounds_jumps(&self) -> Vec<(usize, isize)> {
+        self.blocks
+            .iter()
+            .filter_map(|block| match block.exit {
+                BlockExit::OutOfBounds(target) => Some((block.instrs.end - 1, target)),
+                BlockExit::Block(_) | BlockExit::Terminate => None,
+            })
+            .collect()
+    }
+
+    /// Render the graph in graphviz DOT format. Unreachable blocks are drawn
+    /// dashed.
+    pub fn to_dot(&self) -> String {
+        let reachable = self.reachable_blocks();
+        let mut dot = String::from("digraph program {\n    node [shape=box fontname=monospace];\n");
+        for (block_idx, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for pc in block.instrs.clone() {
+                write!(label, "{}: {}\\l", pc, self.program[pc]).unwrap();
+            }
+            let style = if reachable.contains(block_idx) {
+                ""
+            } else {
+                " style=dashed"
+            };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block_idx, label, style).unwrap();
+            match block.exit {
+                BlockExit::Block(next) => writeln!(dot, "    b{} -> b{};", block_idx, next),
+                BlockExit::Terminate => writeln!(dot, "    b{} -> end;", block_idx),
+                BlockExit::OutOfBounds(target) => writeln!(
+                    dot,
+                    "    \"oob{0}\" [label=\"{0}\" shape=octagon color=red];\n    b{1} -> \"oob{0}\";",
+                    target, block_idx
+                ),
+            }
+            .unwrap();
+        }
+        dot.push_str("    end [shape=doublecircle];\n}\n");
+        dot
     }
 }
 
+#[aoc(day8, part1)]
+pub fn solve_d8_p1(input: &str) -> isize {
+    let program = parse_program(input).unwrap();
+    run_program(&program, BitSet::new(program.len()), 0, 0).1
+}
+
+#[aoc(day8, part2)]
+pub fn solve_d8_p2(input: &str) -> isize {
+    let program = parse_program(input).unwrap();
+    find_repairs(&program)
+        .first()
+        .expect("no single-instruction repair")
+        .accum
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -139,4 +422,52 @@ mod tests {
         bs.insert(65);
         assert!(bs.contains(65));
     }
+
+    const EXAMPLE: &str = r#"nop +0
+acc +1
+jmp +4
+acc +3
+jmp -3
+acc -99
+acc +1
+jmp -4
+acc +6"#;
+
+    #[test]
+    fn test_find_repairs() {
+        let program = parse_program(EXAMPLE).unwrap();
+        assert_eq!(
+            find_repairs(&program),
+            vec![Repair {
+                pc: 7,
+                patched: Instr::Nop(-4),
+                accum: 8,
+            }]
+        );
+        assert_eq!(solve_d8_p2(EXAMPLE), 8);
+    }
+
+    #[test]
+    fn test_control_flow_graph() {
+        let program = parse_program(EXAMPLE).unwrap();
+        let cfg = ControlFlowGraph::new(&program);
+        let block_starts: Vec<_> = cfg.blocks().iter().map(|b| b.instrs.start).collect();
+        assert_eq!(block_starts, vec![0, 1, 3, 5, 6, 8]);
+        assert_eq!(cfg.blocks()[5].exit, BlockExit::Terminate);
+        assert_eq!(cfg.block_containing(7), Some(4));
+        assert_eq!(cfg.loops(), vec![vec![1, 4, 2]]);
+        assert_eq!(cfg.unreachable_instrs(), vec![5, 8]);
+        assert_eq!(cfg.out_of_bounds_jumps(), vec![]);
+
+        let program = parse_program("acc +1\njmp -2\njmp +5").unwrap();
+        let cfg = ControlFlowGraph::new(&program);
+        assert_eq!(cfg.out_of_bounds_jumps(), vec![(1, -1), (2, 7)]);
+        assert_eq!(cfg.loops(), Vec::<Vec<usize>>::new());
+    }
+
+    #[test]
+    fn test_find_repairs_terminating() {
+        let program = parse_program("nop +0\nacc +2").unwrap();
+        assert_eq!(find_repairs(&program), vec![]);
+    }
 }
@@ -1,99 +1,280 @@
 use aoc_runner_derive::aoc;
 
-use std::collections::HashSet;
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+use std::convert::Infallible;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Range;
 
+/// The last `window` numbers seen, with a multiset of their values for
+/// constant time lookups.
 struct Preamble {
-    entries: [usize; 25],
-    entries_start_idx: usize,
-    set: HashSet<usize>,
+    window: usize,
+    entries: VecDeque<usize>,
+    counts: HashMap<usize, usize>,
 }
 
 impl Preamble {
-    // Take the first 25 items from the iterator.
-    fn new<I>(iter: &mut I) -> Option<Self>
-    where
-        I: Iterator<Item = usize>,
-    {
-        let mut entries = [0; 25];
-        let mut set = HashSet::new();
-        let mut count = 0;
-        for (idx, entry) in iter.enumerate().take(25) {
-            count += 1;
-            entries[idx] = entry;
-            set.insert(entry);
-        }
-        if count != 25 {
-            return None;
+    fn new(window: usize) -> Self {
+        Preamble {
+            window,
+            entries: VecDeque::with_capacity(window),
+            counts: HashMap::with_capacity(window),
         }
+    }
 
-        Some(Preamble {
-            entries,
-            entries_start_idx: 0,
-            set,
-        })
+    fn is_full(&self) -> bool {
+        self.entries.len() == self.window
     }
 
-    // Insert a new entry into the preamble letting the oldest one roll off.
+    // Insert a new entry into the preamble letting the oldest one roll off
+    // once the window is full.
     fn insert(&mut self, entry: usize) {
-        self.set.remove(&self.entries[self.entries_start_idx]);
-        self.set.insert(entry);
-        self.entries[self.entries_start_idx] = entry;
-        self.entries_start_idx = (self.entries_start_idx + 1) % 25;
+        if self.is_full() {
+            if let Some(oldest) = self.entries.pop_front() {
+                if let Entry::Occupied(mut occupied) = self.counts.entry(oldest) {
+                    *occupied.get_mut() -= 1;
+                    if *occupied.get() == 0 {
+                        occupied.remove();
+                    }
+                }
+            }
+        }
+        if self.window > 0 {
+            self.entries.push_back(entry);
+            *self.counts.entry(entry).or_insert(0) += 1;
+        }
     }
 
+    // Find two different numbers in the preamble that add up to total.
     fn find_sum_pair(&self, total: usize) -> Option<(usize, usize)> {
-        for idx in 0..25 {
-            let entry = self.entries[(self.entries_start_idx + idx) % 25];
-            if entry <= total {
-                let needed = total - entry;
-                if self.set.contains(&needed) {
-                    return Some((entry, needed));
-                }
+        self.entries.iter().find_map(|&entry| {
+            let needed = total.checked_sub(entry)?;
+            if needed != entry && self.counts.contains_key(&needed) {
+                Some((entry, needed))
+            } else {
+                None
             }
+        })
+    }
+}
+
+/// A number that is not the sum of two different numbers in the window
+/// preceding it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidNumber {
+    /// The position of the number in the source, counting items that
+    /// failed to parse.
+    pub index: usize,
+    pub value: usize,
+}
+
+/// Streams numbers from a fallible source, yielding every invalid number
+/// after the initial preamble. Errors from the source are passed through and
+/// the stream continues after them; they take up an index but don't enter the
+/// preamble.
+pub struct Validator<I> {
+    numbers: I,
+    preamble: Preamble,
+    index: usize,
+}
+
+impl<I, E> Validator<I>
+where
+    I: Iterator<Item = Result<usize, E>>,
+{
+    pub fn new(numbers: I, window: usize) -> Self {
+        Validator {
+            numbers,
+            preamble: Preamble::new(window),
+            index: 0,
         }
-        None
     }
 }
 
-#[aoc(day9, part1)]
-pub fn solve_d9_p1(input: &str) -> usize {
-    let mut iter = input.split('\n').map(|x| -> usize { x.parse().unwrap() });
-    let mut preamble = Preamble::new(&mut iter).unwrap();
-    for entry in iter {
-        if preamble.find_sum_pair(entry).is_none() {
-            return entry;
+impl<I, E> Iterator for Validator<I>
+where
+    I: Iterator<Item = Result<usize, E>>,
+{
+    type Item = Result<InvalidNumber, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let index = self.index;
+            self.index += 1;
+            let value = match self.numbers.next()? {
+                Ok(value) => value,
+                Err(err) => return Some(Err(err)),
+            };
+            let invalid = self.preamble.is_full() && self.preamble.find_sum_pair(value).is_none();
+            self.preamble.insert(value);
+            if invalid {
+                return Some(Ok(InvalidNumber { index, value }));
+            }
         }
-        preamble.insert(entry);
     }
-    panic!("not found");
 }
 
-#[aoc(day9, part2)]
-pub fn solve_d9_p2(input: &str) -> usize {
-    const EXPECTED_SUM: usize = 36845998;
-    let entries: Vec<usize> = input
-        .split('\n')
-        .map(|x| -> usize { x.parse().unwrap() })
-        .collect();
+/// Every invalid number in `numbers` using a preamble of `window` entries.
+pub fn invalid_numbers<I>(numbers: I, window: usize) -> impl Iterator<Item = InvalidNumber>
+where
+    I: IntoIterator<Item = usize>,
+{
+    Validator::new(numbers.into_iter().map(Ok::<_, Infallible>), window).map(
+        |result| match result {
+            Ok(invalid) => invalid,
+            Err(never) => match never {},
+        },
+    )
+}
 
+/// Every invalid number in a newline separated list read from `reader`.
+/// Lines that fail to parse are reported as `InvalidData` errors.
+pub fn invalid_numbers_from_reader<R: Read>(
+    reader: R,
+    window: usize,
+) -> impl Iterator<Item = io::Result<InvalidNumber>> {
+    let numbers = BufReader::new(reader).lines().map(|line| {
+        let line = line?;
+        line.trim()
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    });
+    Validator::new(numbers, window)
+}
+
+fn parse_numbers(input: &str) -> Vec<usize> {
+    input.split('\n').map(|x| x.parse().unwrap()).collect()
+}
+
+#[aoc(day9, part1)]
+pub fn solve_d9_p1(input: &str) -> usize {
+    invalid_numbers(parse_numbers(input), 25)
+        .next()
+        .expect("not found")
+        .value
+}
+
+/// A run of at least two contiguous numbers that add up to a target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContiguousSum {
+    pub range: Range<usize>,
+    pub min: usize,
+    pub max: usize,
+}
+
+impl ContiguousSum {
+    /// The encryption weakness: the sum of the smallest and largest numbers
+    /// in the range.
+    pub fn weakness(&self) -> usize {
+        self.min + self.max
+    }
+}
+
+/// Find the first run of at least two contiguous entries that sum to
+/// `target`, or `None` if there isn't one.
+pub fn find_contiguous_sum(entries: &[usize], target: usize) -> Option<ContiguousSum> {
     let mut sum = 0;
     let mut start_idx = 0;
-    let mut end_idx = 0;
-    loop {
-        if sum < EXPECTED_SUM {
-            sum += entries[end_idx];
-            end_idx += 1;
-        } else if sum > EXPECTED_SUM {
+    for (end_idx, &entry) in entries.iter().enumerate() {
+        sum += entry;
+        while sum > target && start_idx < end_idx {
             sum -= entries[start_idx];
             start_idx += 1;
-        } else {
-            let (min, max) = &entries[start_idx..end_idx]
-                .iter()
-                .copied()
-                .fold((usize::MAX, 0), |(min, max), entry| {
-                    (std::cmp::min(min, entry), std::cmp::max(max, entry))
-                });
-            return min + max;
         }
+        if sum == target && end_idx > start_idx {
+            let range = start_idx..end_idx + 1;
+            let run = &entries[range.clone()];
+            return Some(ContiguousSum {
+                min: run.iter().copied().min()?,
+                max: run.iter().copied().max()?,
+                range,
+            });
+        }
+    }
+    None
+}
+
+#[aoc(day9, part2)]
+pub fn solve_d9_p2(input: &str) -> usize {
+    let entries = parse_numbers(input);
+    let invalid = invalid_numbers(entries.iter().copied(), 25)
+        .next()
+        .expect("no invalid number");
+    find_contiguous_sum(&entries, invalid.value)
+        .expect("no contiguous range")
+        .weakness()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"35
+20
+15
+25
+47
+40
+62
+55
+65
+95
+102
+117
+150
+182
+127
+219
+299
+277
+309
+576"#;
+
+    #[test]
+    fn test_invalid_numbers() {
+        let invalid: Vec<_> = invalid_numbers(parse_numbers(EXAMPLE), 5).collect();
+        assert_eq!(
+            invalid,
+            vec![InvalidNumber {
+                index: 14,
+                value: 127
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_contiguous_sum() {
+        let entries = parse_numbers(EXAMPLE);
+        let found = find_contiguous_sum(&entries, 127).unwrap();
+        assert_eq!(found.range, 2..6);
+        assert_eq!(found.weakness(), 62);
+        assert_eq!(find_contiguous_sum(&entries, 36), None);
+        assert_eq!(find_contiguous_sum(&entries, 1_000_000), None);
+    }
+
+    #[test]
+    fn test_invalid_numbers_from_reader() {
+        let input = "1\n2\n3\n4\n100\nfoo\n7\n";
+        let mut iter = invalid_numbers_from_reader(input.as_bytes(), 2);
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            InvalidNumber { index: 3, value: 4 }
+        );
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            InvalidNumber {
+                index: 4,
+                value: 100
+            }
+        );
+        assert_eq!(
+            iter.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            InvalidNumber { index: 6, value: 7 }
+        );
+        assert!(iter.next().is_none());
     }
 }
@@ -1,3 +1,4 @@
+pub mod automaton;
 pub mod day1;
 pub mod day10;
 pub mod day11;
@@ -23,6 +24,7 @@ pub mod day6;
 pub mod day7;
 pub mod day8;
 pub mod day9;
+pub mod number_theory;
 
 aoc_runner_derive::aoc_lib! { year = 2020 }
 
@@ -30,3 +32,14 @@ fn split_once<'a>(input: &'a str, delimeter: &str) -> Option<(&'a str, &'a str)>
     let idx = input.find(delimeter)?;
     Some((&input[..idx], &input[idx + delimeter.len()..]))
 }
+
+// A repeatable xorshift sequence for tests that need random input.
+#[cfg(test)]
+fn xorshift(mut seed: u64) -> impl FnMut() -> u64 {
+    move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    }
+}
@@ -0,0 +1,224 @@
+//! Modular arithmetic helpers. Everything works on `u128` and never
+//! overflows: products are reduced as they're computed and results that
+//! can't be represented are reported as errors.
+
+use std::fmt;
+
+pub fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// `a * b mod m` without overflowing, even when `a * b` doesn't fit in a
+/// `u128`.
+pub fn mul_mod(a: u128, b: u128, m: u128) -> u128 {
+    let (mut a, mut b) = (a % m, b % m);
+    if let Some(product) = a.checked_mul(b) {
+        return product % m;
+    }
+    // Double and add. Both operands are below m so (m - x) is a safe way to
+    // test whether x + y wraps past m.
+    let mut result = 0;
+    while b > 0 {
+        if b & 1 == 1 {
+            result = add_mod(result, a, m);
+        }
+        a = add_mod(a, a, m);
+        b >>= 1;
+    }
+    result
+}
+
+fn add_mod(a: u128, b: u128, m: u128) -> u128 {
+    if a >= m - b {
+        a - (m - b)
+    } else {
+        a + b
+    }
+}
+
+/// The modular inverse t such that a*t ≡ 1 mod n, or `None` if a and n
+/// aren't coprime.
+// https://en.wikipedia.org/wiki/Extended_Euclidean_algorithm#Modular_integers
+pub fn mod_inverse(a: u128, n: u128) -> Option<u128> {
+    if n == 1 {
+        return Some(0);
+    }
+    // The coefficients are kept reduced mod n so they can stay unsigned.
+    let (mut t, mut newt) = (0, 1);
+    let (mut r, mut newr) = (n, a % n);
+    while newr != 0 {
+        let q = r / newr;
+        let next = add_mod(t, (n - mul_mod(q, newt, n)) % n, n);
+        t = newt;
+        newt = next;
+        let next = r - q * newr;
+        r = newr;
+        newr = next;
+    }
+    if r == 1 {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// The set of integers x with x ≡ residue mod modulus.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Congruence {
+    pub residue: u128,
+    pub modulus: u128,
+}
+
+impl Congruence {
+    /// The residue is reduced mod `modulus`.
+    pub fn new(residue: u128, modulus: u128) -> Option<Self> {
+        if modulus == 0 {
+            return None;
+        }
+        Some(Congruence {
+            residue: residue % modulus,
+            modulus,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CrtError {
+    /// The congruence at this index has a modulus of zero.
+    ZeroModulus { index: usize },
+    /// The congruence at this index contradicts the ones before it.
+    Inconsistent { index: usize },
+    /// The combined modulus is too large for a `u128`.
+    Overflow { index: usize },
+}
+
+impl fmt::Display for CrtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CrtError::ZeroModulus { index } => write!(f, "congruence {} has modulus 0", index),
+            CrtError::Inconsistent { index } => write!(
+                f,
+                "congruence {} contradicts the congruences before it",
+                index
+            ),
+            CrtError::Overflow { index } => {
+                write!(f, "combined modulus overflows at congruence {}", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CrtError {}
+
+/// Solve a system of congruences with the chinese remainder theorem. The
+/// moduli don't need to be pairwise coprime. The solution is every x that
+/// satisfies all of them, which is a single congruence modulo the lcm of the
+/// moduli. An empty system is satisfied by every integer (x ≡ 0 mod 1).
+pub fn crt<I>(congruences: I) -> Result<Congruence, CrtError>
+where
+    I: IntoIterator<Item = Congruence>,
+{
+    let mut solution = Congruence {
+        residue: 0,
+        modulus: 1,
+    };
+    for (index, c) in congruences.into_iter().enumerate() {
+        if c.modulus == 0 {
+            return Err(CrtError::ZeroModulus { index });
+        }
+        let (a1, m1) = (solution.residue, solution.modulus);
+        let (a2, m2) = (c.residue % c.modulus, c.modulus);
+        let g = gcd(m1, m2);
+        // x = a1 + m1*k, so we need m1*k ≡ a2 - a1 mod m2, which is only
+        // solvable when g divides a2 - a1.
+        let diff = (a2 + (m2 - a1 % m2)) % m2;
+        if diff % g != 0 {
+            return Err(CrtError::Inconsistent { index });
+        }
+        let reduced = m2 / g;
+        let lcm = (m1 / g)
+            .checked_mul(m2)
+            .ok_or(CrtError::Overflow { index })?;
+        let inv = mod_inverse(m1 / g, reduced).expect("m1/g and m2/g are coprime");
+        let k = mul_mod(diff / g, inv, reduced);
+        // a1 < m1 and k < m2/g so this is at most lcm - 1.
+        solution = Congruence {
+            residue: a1 + m1 * k,
+            modulus: lcm,
+        };
+    }
+    Ok(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn congruences(pairs: &[(u128, u128)]) -> Vec<Congruence> {
+        pairs
+            .iter()
+            .map(|&(residue, modulus)| Congruence { residue, modulus })
+            .collect()
+    }
+
+    #[test]
+    fn test_inverse() {
+        assert_eq!(mod_inverse(3, 11), Some(4));
+        assert_eq!(mod_inverse(4, 6), None);
+        let p = u128::MAX - 158; // The largest prime below 2^128.
+        let inv = mod_inverse(2, p).unwrap();
+        assert_eq!(mul_mod(inv, 2, p), 1);
+    }
+
+    #[test]
+    fn test_crt() {
+        assert_eq!(
+            crt(congruences(&[(2, 3), (3, 5), (2, 7)])),
+            Ok(Congruence {
+                residue: 23,
+                modulus: 105
+            })
+        );
+        // Not coprime, but consistent.
+        assert_eq!(
+            crt(congruences(&[(2, 4), (4, 6)])),
+            Ok(Congruence {
+                residue: 10,
+                modulus: 12
+            })
+        );
+        assert_eq!(
+            crt(congruences(&[(1, 4), (2, 6)])),
+            Err(CrtError::Inconsistent { index: 1 })
+        );
+        assert_eq!(
+            crt(congruences(&[(1, 4), (0, 0)])),
+            Err(CrtError::ZeroModulus { index: 1 })
+        );
+        assert_eq!(
+            crt(vec![]),
+            Ok(Congruence {
+                residue: 0,
+                modulus: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_crt_large() {
+        // Two primes near 2^64 whose product only fits in a u128.
+        let (p, q) = (18446744073709551557, 18446744073709551533);
+        let x = 340282366920938463463374607431768211000u128 % (p * q);
+        let solution = crt(congruences(&[(x % p, p), (x % q, q)])).unwrap();
+        assert_eq!(solution, Congruence::new(x, p * q).unwrap());
+        assert_eq!(
+            crt(congruences(&[(0, p), (0, q), (0, 3)])),
+            Err(CrtError::Overflow { index: 2 })
+        );
+    }
+}